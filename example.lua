@@ -9,27 +9,15 @@ function Vec(x, y)
     return vector
 end
 
-local function load_shader(name,vertex_path,fragment_path)
-    local v = read_file(vertex_path)
-    if v == "" then
-        return
-    end
-    local f = read_file(fragment_path)
-    if f == "" then
-        return
-    end
-    material_load_shader(name,v,f)
-end
-local quad = {
-    {x = -0.5, y = -0.5, tx = 0.0, ty = 0.0},
-    {x = 0.5, y = -0.5, tx = 1.0, ty = 0.0},
-    {x = -0.5, y = 0.5, tx = 0.0, ty = 1.0},
-    {x = 0.5, y = 0.5, tx = 1.0, ty = 1.0},
-}
 function load()
-    load_shader("default_shader","./resources/Sprite/vertex.vert","./resources/Sprite/fragment.frag")
-    material_load_mesh("default_quad_mesh_strip",quad)
-    material_load_texture("default_texture","./resources/image.png")
+    load_manifest("./resources/manifest.json")
 
     set_camera_position(Vec(0.5,0.5) + get_camera_position())
-end
\ No newline at end of file
+end
+function draw()
+    for i = 0, 9 do
+        for j = 0, 9 do
+            draw_sprite("default_texture", {x = i * 0.2 - 0.9, y = j * 0.2 - 0.9, scale_x = 0.15, scale_y = 0.15, a = 0.5})
+        end
+    end
+end
@@ -1,6 +1,5 @@
 #version 330 core
-layout (location = 0) in vec2 position;
-layout (location = 1) in vec2 texCoord;
+#include "../common/planar_vertex.glsl"
 out vec2 TexCoord;
 uniform mat3x2 transform;
 void main()
@@ -1,5 +1,21 @@
 #version 330 core
 
+#pragma stage vertex
+#include "../common/planar_vertex.glsl"
+out vec2 tex_coord;
+
+uniform mat3x2 transform;
+
+#include "../common/matrices.glsl"
+
+void main()
+{
+    gl_Position =  Projection * (View * vec4(transform*vec3(position,1.0), 1.0, 1.0));
+
+    tex_coord = texCoord; // pass texture coordinates to the fragment shader
+}
+
+#pragma stage fragment
 in vec2 tex_coord;
 out vec4 frag_color;
 
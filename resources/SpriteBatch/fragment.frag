@@ -0,0 +1,11 @@
+#version 330 core
+
+in vec2 tex_coord;
+in vec4 color;
+out vec4 frag_color;
+
+uniform sampler2D sprite_texture;
+
+void main() {
+    frag_color = texture(sprite_texture, tex_coord) * color;
+}
@@ -0,0 +1,18 @@
+#version 330 core
+#include "../common/planar_vertex.glsl"
+// Per instance
+layout (location = 2) in mat3x2 transform;
+layout (location = 5) in vec4 uv_rect;
+layout (location = 6) in vec4 tint;
+out vec2 tex_coord;
+out vec4 color;
+
+#include "../common/matrices.glsl"
+
+void main()
+{
+    gl_Position =  Projection * (View * vec4(transform*vec3(position,1.0), 1.0, 1.0));
+
+    tex_coord = texCoord * uv_rect.zw + uv_rect.xy;
+    color = tint;
+}
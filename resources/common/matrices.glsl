@@ -0,0 +1,5 @@
+layout (std140) uniform Matrices
+{
+    mat4 Projection;
+    mat4 View;
+};
@@ -0,0 +1,2 @@
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec2 texCoord;
@@ -0,0 +1,175 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, Path};
+
+/// Implements `rgms::mesh::MeshVertex` for a `#[repr(C)]` struct.
+///
+/// Every field takes the next attribute location unless it has `#[location(n)]`,
+/// locations are relative to the base location the layout is enabled at.
+/// `#[normalized]` maps integer fields to [0, 1] / [-1, 1] floats and
+/// `#[integer]` keeps them as integers (`ivec`/`uvec` in GLSL).
+/// The generated code refers to the engine as `crate`, outside of it the struct
+/// needs `#[mesh_vertex(crate = rgms)]`.
+#[proc_macro_derive(MeshVertex, attributes(mesh_vertex, location, normalized, integer))]
+pub fn derive_mesh_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    let mut krate: Path = syn::parse_quote!(crate);
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("mesh_vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `crate = path`"))
+            }
+        })?;
+    }
+
+    let mut repr_c = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            repr_c |= meta.path.is_ident("C");
+            Ok(())
+        })?;
+    }
+    if !repr_c {
+        return Err(syn::Error::new_spanned(name, "MeshVertex requires #[repr(C)] so field offsets match the GPU layout"));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(name, "MeshVertex can only be derived for structs with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(name, "MeshVertex can only be derived for structs")),
+    };
+
+    let mut next_location = quote!(0u32);
+    let mut locations = Vec::new();
+    let mut setup = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+
+        let mut location = None;
+        let mut normalized = false;
+        let mut integer = false;
+        for attr in &field.attrs {
+            if attr.path().is_ident("location") {
+                location = Some(attr.parse_args::<LitInt>()?.base10_parse::<u32>()?);
+            } else if attr.path().is_ident("normalized") {
+                normalized = true;
+            } else if attr.path().is_ident("integer") {
+                integer = true;
+            }
+        }
+        if normalized && integer {
+            return Err(syn::Error::new_spanned(ident, "a field can't be both #[normalized] and #[integer]"));
+        }
+
+        let location = match location {
+            Some(x) => quote!(#x),
+            None => next_location.clone(),
+        };
+        let attribute_locations = quote!(<#ty as #krate::mesh::VertexAttribute>::LOCATIONS);
+        next_location = quote!((#location + #attribute_locations));
+        locations.push(next_location.clone());
+
+        setup.push(quote! {
+            #krate::mesh::enable_attribute::<#ty>(
+                base_location + #location,
+                stride,
+                offset + ::std::mem::offset_of!(Self, #ident),
+                #normalized,
+                #integer,
+                divisor,
+            );
+        });
+    }
+
+    Ok(quote! {
+        impl #krate::mesh::MeshVertex for #name {
+            const LOCATIONS: u32 = {
+                let mut count = 0u32;
+                #(
+                    if #locations > count {
+                        count = #locations;
+                    }
+                )*
+                count
+            };
+            unsafe fn enable_vertex_attrib(base_location: u32, divisor: u32, offset: usize) {
+                let stride = ::std::mem::size_of::<Self>() as i32;
+                #(#setup)*
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_error(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fields_take_consecutive_locations() {
+        let output = expand(&syn::parse_quote! {
+            #[repr(C)]
+            struct Vertex {
+                position: [f32; 2],
+                #[location(4)]
+                #[normalized]
+                color: [u8; 4],
+            }
+        }).unwrap().to_string();
+        assert!(output.contains("impl crate :: mesh :: MeshVertex for Vertex"), "{}", output);
+        // The first field starts at 0, the second is placed explicitly and normalized
+        assert!(output.contains("base_location + 0u32 , stride , offset + :: std :: mem :: offset_of ! (Self , position) , false , false"), "{}", output);
+        assert!(output.contains("base_location + 4u32 , stride , offset + :: std :: mem :: offset_of ! (Self , color) , true , false"), "{}", output);
+    }
+
+    #[test]
+    fn crate_path_can_be_set() {
+        let output = expand(&syn::parse_quote! {
+            #[repr(C)]
+            #[mesh_vertex(crate = ::rgms)]
+            struct Vertex {
+                position: [f32; 2],
+            }
+        }).unwrap().to_string();
+        assert!(output.contains("impl :: rgms :: mesh :: MeshVertex for Vertex"), "{}", output);
+        assert!(!output.contains("crate ::"), "{}", output);
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        assert!(expand_error(syn::parse_quote! {
+            struct Vertex { position: [f32; 2] }
+        }).contains("requires #[repr(C)]"));
+        assert!(expand_error(syn::parse_quote! {
+            #[repr(C)]
+            struct Vertex { #[normalized] #[integer] color: [u8; 4] }
+        }).contains("both #[normalized] and #[integer]"));
+        assert!(expand_error(syn::parse_quote! {
+            #[repr(C)]
+            struct Vertex([f32; 2]);
+        }).contains("named fields"));
+        assert!(expand_error(syn::parse_quote! {
+            #[repr(C)]
+            #[mesh_vertex(path = rgms)]
+            struct Vertex { position: [f32; 2] }
+        }).contains("expected `crate = path`"));
+    }
+}
@@ -0,0 +1,564 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::Deserialize;
+
+use crate::atlas::AtlasRegion;
+use crate::blend::BlendMode;
+use crate::container::Module2D;
+use crate::handle::Handle;
+use crate::resource_manager::{ResourceManager, TextureHandle};
+use crate::shader::Shader;
+use crate::sprite_batch::{SpriteBatch, SpriteInstance, SpriteState};
+use crate::transform::Transform2D;
+use crate::vfs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayMode {
+    Loop,
+    PingPong,
+    /// Stops on the last frame
+    Once,
+}
+
+impl PlayMode {
+    pub fn from_name(name: &str) -> Option<PlayMode> {
+        match name {
+            "loop" => Some(PlayMode::Loop),
+            "ping_pong" => Some(PlayMode::PingPong),
+            "once" => Some(PlayMode::Once),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationFrame {
+    /// Texture or `"atlas/region"` key
+    pub sprite: String,
+    /// Part of the sprite shown, in its own [0, 1] coordinates
+    pub uv_rect: [f32; 4],
+    /// Seconds
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub frames: Vec<AnimationFrame>,
+    pub mode: PlayMode,
+}
+
+impl AnimationClip {
+    /// One frame per sprite key, e.g. atlas regions
+    pub fn from_sprites(sprites: &[String], duration: f32, mode: PlayMode) -> Self {
+        let frames = sprites.iter()
+            .map(|x| AnimationFrame { sprite: x.clone(), uv_rect: [0.0, 0.0, 1.0, 1.0], duration })
+            .collect();
+        Self { frames, mode }
+    }
+    /// `count` cells starting at `first` of a sheet sliced in `columns` x `rows`, counted row by row from the top left
+    pub fn from_grid(texture: &str, columns: u32, rows: u32, first: u32, count: u32, duration: f32, mode: PlayMode) -> Self {
+        let frames = (first..first + count)
+            .map(|i| {
+                let (column, row) = (i % columns, i / columns);
+                AnimationFrame {
+                    sprite: texture.to_string(),
+                    // Textures are flipped on load, so the top row has the highest v
+                    uv_rect: [
+                        column as f32 / columns as f32,
+                        (rows - 1 - row.min(rows - 1)) as f32 / rows as f32,
+                        1.0 / columns as f32,
+                        1.0 / rows as f32,
+                    ],
+                    duration,
+                }
+            })
+            .collect();
+        Self { frames, mode }
+    }
+}
+
+#[derive(Deserialize)]
+struct AsepriteRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteFrame {
+    frame: AsepriteRect,
+    /// Milliseconds
+    duration: u32,
+}
+
+/// Aseprite exports frames either as an array or as a name -> frame map, in frame order
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AsepriteFrames {
+    Array(Vec<AsepriteFrame>),
+    Hash(serde_json::Map<String, serde_json::Value>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteMeta {
+    size: AsepriteSize,
+    #[serde(default)]
+    frame_tags: Vec<AsepriteTag>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSheet {
+    frames: AsepriteFrames,
+    meta: AsepriteMeta,
+}
+
+/// Loads the clips of an Aseprite JSON export, one per tag or a single "default" clip without tags.
+/// `texture` is the key the exported sheet image was loaded under
+pub fn load_aseprite(path: &str, texture: &str) -> Result<HashMap<String, AnimationClip>, String> {
+    let text = vfs::read_to_string(path)
+        .map_err(|e| format!("Failed to read \"{}\": {}", path, e))?;
+    let sheet: AsepriteSheet = serde_json::from_str(&text)
+        .map_err(|e| format!("Invalid Aseprite sheet \"{}\": {}", path, e))?;
+
+    let frames = match sheet.frames {
+        AsepriteFrames::Array(x) => x,
+        AsepriteFrames::Hash(x) => x.into_iter()
+            .map(|(name, value)| serde_json::from_value(value).map_err(|e| format!("Invalid frame \"{}\" in \"{}\": {}", name, path, e)))
+            .collect::<Result<_, _>>()?,
+    };
+    let (width, height) = (sheet.meta.size.w as f32, sheet.meta.size.h as f32);
+    let frames: Vec<AnimationFrame> = frames.iter()
+        .map(|x| AnimationFrame {
+            sprite: texture.to_string(),
+            uv_rect: [
+                x.frame.x as f32 / width,
+                (height - (x.frame.y + x.frame.h) as f32) / height,
+                x.frame.w as f32 / width,
+                x.frame.h as f32 / height,
+            ],
+            duration: x.duration as f32 / 1000.0,
+        })
+        .collect();
+
+    let mut clips = HashMap::new();
+    if sheet.meta.frame_tags.is_empty() {
+        clips.insert("default".to_string(), AnimationClip { frames, mode: PlayMode::Loop });
+        return Ok(clips);
+    }
+    for tag in sheet.meta.frame_tags {
+        if tag.from > tag.to || tag.to >= frames.len() {
+            return Err(format!("Tag \"{}\" in \"{}\" is out of range", tag.name, path));
+        }
+        let mut clip = frames[tag.from..=tag.to].to_vec();
+        if tag.direction == "reverse" || tag.direction == "pingpong_reverse" {
+            clip.reverse();
+        }
+        let mode = if tag.direction.starts_with("pingpong") { PlayMode::PingPong } else { PlayMode::Loop };
+        clips.insert(tag.name, AnimationClip { frames: clip, mode });
+    }
+    Ok(clips)
+}
+
+/// Animator component of a scene entity, shared so scripts keep controlling it once attached
+pub type SharedAnimator = Rc<RefCell<Animator>>;
+
+/// Plays one clip at a time, drawing its current frame through the sprite batch
+pub struct Animator {
+    clips: HashMap<String, AnimationClip>,
+    clip: Option<String>,
+    frame: usize,
+    elapsed: f32,
+    forward: bool,
+    playing: bool,
+    pub speed: f32,
+    shader: String,
+    pub blend: BlendMode,
+    /// Clips that ended (once) or completed a cycle (loop, ping-pong) since the last `take_finished`
+    finished: Vec<String>,
+    resolved: Cell<Option<ResolvedFrame>>,
+}
+
+/// Handles the current frame resolved to, valid while the frame and the resource manager's revision are unchanged
+#[derive(Clone, Copy)]
+struct ResolvedFrame {
+    revision: u64,
+    frame: usize,
+    texture: TextureHandle,
+    region: Option<AtlasRegion>,
+    shader: Handle<Shader>,
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Animator {
+    pub fn new() -> Self {
+        Self {
+            clips: HashMap::new(),
+            clip: None,
+            frame: 0,
+            elapsed: 0.0,
+            forward: true,
+            playing: false,
+            speed: 1.0,
+            shader: SpriteBatch::DEFAULT_SHADER.to_string(),
+            blend: BlendMode::Alpha,
+            finished: Vec::new(),
+            resolved: Cell::new(None),
+        }
+    }
+
+    /// Replacing the clip that is playing restarts it, the new one may have fewer frames
+    pub fn add_clip(&mut self, name: &str, clip: AnimationClip) {
+        self.clips.insert(name.to_string(), clip);
+        if self.clip.as_deref() == Some(name) {
+            self.resolved.set(None);
+            self.frame = 0;
+            self.elapsed = 0.0;
+            self.forward = true;
+        }
+    }
+
+    /// Keeps the current position if `name` is already playing, unless `restart` is set
+    pub fn play(&mut self, name: &str, restart: bool) -> Result<(), String> {
+        if !self.clips.contains_key(name) {
+            return Err(format!("Animation clip \"{}\" not found", name));
+        }
+        if !restart && self.playing && self.clip.as_deref() == Some(name) {
+            return Ok(());
+        }
+        self.clip = Some(name.to_string());
+        self.resolved.set(None);
+        self.frame = 0;
+        self.elapsed = 0.0;
+        self.forward = true;
+        self.playing = true;
+        Ok(())
+    }
+    pub fn set_shader(&mut self, key: &str) {
+        self.shader = key.to_string();
+        self.resolved.set(None);
+    }
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+    pub fn clip(&self) -> Option<&str> {
+        self.clip.as_deref()
+    }
+    pub fn frame_index(&self) -> usize {
+        self.frame
+    }
+    pub fn current_frame(&self) -> Option<&AnimationFrame> {
+        self.clips.get(self.clip.as_ref()?)?.frames.get(self.frame)
+    }
+    pub fn take_finished(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.finished)
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        if !self.playing {
+            return;
+        }
+        let Some(name) = &self.clip else {
+            return;
+        };
+        let Some(clip) = self.clips.get(name) else {
+            return;
+        };
+        let count = clip.frames.len();
+        if count == 0 {
+            return;
+        }
+        self.elapsed += delta * self.speed;
+        loop {
+            // Zero length frames would never let the loop end
+            let duration = clip.frames[self.frame].duration.max(0.001);
+            if self.elapsed < duration {
+                break;
+            }
+            self.elapsed -= duration;
+            match clip.mode {
+                PlayMode::Loop => {
+                    self.frame = (self.frame + 1) % count;
+                    if self.frame == 0 {
+                        self.finished.push(name.clone());
+                    }
+                }
+                PlayMode::PingPong => {
+                    if count == 1 {
+                        self.finished.push(name.clone());
+                        continue;
+                    }
+                    if self.frame + 1 == count {
+                        self.forward = false;
+                    }
+                    if self.forward {
+                        self.frame += 1;
+                    } else {
+                        self.frame -= 1;
+                    }
+                    // Back at the start completes a cycle
+                    if self.frame == 0 {
+                        self.forward = true;
+                        self.finished.push(name.clone());
+                    }
+                }
+                PlayMode::Once => {
+                    if self.frame + 1 == count {
+                        self.playing = false;
+                        self.elapsed = 0.0;
+                        self.finished.push(name.clone());
+                        break;
+                    }
+                    self.frame += 1;
+                }
+            }
+        }
+    }
+
+    /// Queues the current frame, `instance.uv_rect` is relative to the frame. Skipped while its resources aren't loaded
+    pub fn draw(&self, resource_manager: &mut ResourceManager, instance: SpriteInstance) {
+        if let Some((state, instance)) = self.sprite(resource_manager, instance) {
+            resource_manager.sprite_batch_mut().draw(state, instance);
+        }
+    }
+
+    /// Looks the frame's keys up again only after it changed or resources were added, replaced or unloaded
+    fn resolve_frame(&self, resource_manager: &ResourceManager, frame: &AnimationFrame) -> Option<ResolvedFrame> {
+        let revision = resource_manager.revision();
+        if let Some(x) = self.resolved.get().filter(|x| x.revision == revision && x.frame == self.frame) {
+            return Some(x);
+        }
+        let (texture, region) = resource_manager.resolve_sprite(&frame.sprite)?;
+        let shader = resource_manager.handle::<Shader>(&self.shader)?;
+        let resolved = ResolvedFrame { revision, frame: self.frame, texture, region, shader };
+        self.resolved.set(Some(resolved));
+        Some(resolved)
+    }
+
+    /// What `draw` queues, None without a frame or while its resources aren't loaded
+    pub fn sprite(&self, resource_manager: &ResourceManager, mut instance: SpriteInstance) -> Option<(SpriteState, SpriteInstance)> {
+        let frame = self.current_frame()?;
+        let ResolvedFrame { texture, region, shader, .. } = self.resolve_frame(resource_manager, frame)?;
+        let uv = crate::atlas::sub_rect(frame.uv_rect, instance.uv_rect);
+        instance.uv_rect = match region {
+            Some(region) => region.sub_rect(uv),
+            None => uv,
+        };
+        let state = SpriteState {
+            shader,
+            texture,
+            sampler: None,
+            blend: self.blend,
+        };
+        Some((state, instance))
+    }
+}
+
+impl Module2D for Animator {
+    fn on_update(&mut self, delta: f32) {
+        self.advance(delta);
+    }
+    fn on_render(&mut self, transform: &mut Transform2D, resource_manager: &mut ResourceManager) {
+        self.draw(resource_manager, SpriteInstance::new(transform.transformation_matrix()));
+    }
+    fn wants_parallel_update(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::TempDir;
+
+    fn clip(count: usize, mode: PlayMode) -> AnimationClip {
+        let sprites: Vec<String> = (0..count).map(|i| format!("frame{}", i)).collect();
+        AnimationClip::from_sprites(&sprites, 1.0, mode)
+    }
+
+    fn playing(count: usize, mode: PlayMode) -> Animator {
+        let mut animator = Animator::new();
+        animator.add_clip("clip", clip(count, mode));
+        animator.play("clip", false).unwrap();
+        animator
+    }
+
+    /// Frame index after each of `steps` one second updates
+    fn frames(animator: &mut Animator, steps: usize) -> Vec<usize> {
+        (0..steps).map(|_| {
+            animator.advance(1.0);
+            animator.frame_index()
+        }).collect()
+    }
+
+    #[test]
+    fn loop_wraps_and_reports_cycles() {
+        let mut animator = playing(3, PlayMode::Loop);
+        assert_eq!(frames(&mut animator, 4), [1, 2, 0, 1]);
+        assert_eq!(animator.take_finished(), ["clip"]);
+        assert!(animator.take_finished().is_empty());
+        assert!(animator.is_playing());
+    }
+
+    #[test]
+    fn ping_pong_turns_at_both_ends() {
+        let mut animator = playing(3, PlayMode::PingPong);
+        assert_eq!(frames(&mut animator, 6), [1, 2, 1, 0, 1, 2]);
+        assert_eq!(animator.take_finished(), ["clip"]);
+
+        let mut single = playing(1, PlayMode::PingPong);
+        assert_eq!(frames(&mut single, 2), [0, 0]);
+        assert_eq!(single.take_finished().len(), 2);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut animator = playing(3, PlayMode::Once);
+        assert_eq!(frames(&mut animator, 4), [1, 2, 2, 2]);
+        assert!(!animator.is_playing());
+        assert_eq!(animator.take_finished(), ["clip"]);
+    }
+
+    #[test]
+    fn large_deltas_skip_frames() {
+        let mut animator = playing(4, PlayMode::Loop);
+        animator.speed = 2.0;
+        animator.advance(1.25);
+        assert_eq!(animator.frame_index(), 2);
+        // The remaining half of frame 2 carries over
+        animator.advance(0.25);
+        assert_eq!(animator.frame_index(), 3);
+    }
+
+    #[test]
+    fn stopped_and_unknown_clips_dont_advance() {
+        let mut animator = playing(3, PlayMode::Loop);
+        animator.stop();
+        animator.advance(1.0);
+        assert_eq!(animator.frame_index(), 0);
+        assert!(animator.play("missing", false).is_err());
+        assert_eq!(animator.clip(), Some("clip"));
+    }
+
+    #[test]
+    fn play_keeps_position_unless_restarted() {
+        let mut animator = playing(3, PlayMode::Loop);
+        animator.advance(1.0);
+        animator.play("clip", false).unwrap();
+        assert_eq!(animator.frame_index(), 1);
+        animator.play("clip", true).unwrap();
+        assert_eq!(animator.frame_index(), 0);
+    }
+
+    #[test]
+    fn replacing_the_playing_clip_restarts_it() {
+        let mut animator = playing(4, PlayMode::Loop);
+        frames(&mut animator, 3);
+        animator.add_clip("clip", clip(2, PlayMode::Loop));
+        assert_eq!(animator.frame_index(), 0);
+        assert_eq!(frames(&mut animator, 3), [1, 0, 1]);
+        assert_eq!(animator.current_frame().unwrap().sprite, "frame1");
+    }
+
+    #[test]
+    fn grid_cells_count_from_the_top_left() {
+        let clip = AnimationClip::from_grid("sheet", 4, 2, 3, 2, 0.5, PlayMode::Once);
+        assert_eq!(clip.frames.len(), 2);
+        assert_eq!(clip.frames[0].sprite, "sheet");
+        assert_eq!(clip.frames[0].duration, 0.5);
+        // Cell 3 is the last of the top row, which has the highest v
+        assert_eq!(clip.frames[0].uv_rect, [0.75, 0.5, 0.25, 0.5]);
+        assert_eq!(clip.frames[1].uv_rect, [0.0, 0.0, 0.25, 0.5]);
+        // Cells past the sheet stay on its last row
+        let clip = AnimationClip::from_grid("sheet", 2, 1, 0, 3, 0.5, PlayMode::Loop);
+        assert_eq!(clip.frames[2].uv_rect, [0.0, 0.0, 0.5, 1.0]);
+    }
+
+    const FRAMES: &str = r#"[
+        {"frame": {"x": 0, "y": 0, "w": 16, "h": 16}, "duration": 100},
+        {"frame": {"x": 16, "y": 0, "w": 16, "h": 16}, "duration": 200},
+        {"frame": {"x": 0, "y": 16, "w": 16, "h": 16}, "duration": 300}
+    ]"#;
+
+    #[test]
+    fn aseprite_tags_become_clips() {
+        let dir = TempDir::new("aseprite-tags");
+        let path = dir.write("sheet.json", format!(r#"{{"frames": {}, "meta": {{"size": {{"w": 32, "h": 32}}, "frameTags": [
+            {{"name": "walk", "from": 0, "to": 1, "direction": "forward"}},
+            {{"name": "back", "from": 0, "to": 2, "direction": "reverse"}},
+            {{"name": "bounce", "from": 1, "to": 2, "direction": "pingpong"}}
+        ]}}}}"#, FRAMES));
+        let clips = load_aseprite(&path, "sheet").unwrap();
+
+        let walk = &clips["walk"];
+        assert_eq!(walk.mode, PlayMode::Loop);
+        assert_eq!(walk.frames.len(), 2);
+        assert_eq!(walk.frames[0].sprite, "sheet");
+        assert_eq!(walk.frames[1].duration, 0.2);
+        // The top of the image has the highest v
+        assert_eq!(walk.frames[0].uv_rect, [0.0, 0.5, 0.5, 0.5]);
+        assert_eq!(walk.frames[1].uv_rect, [0.5, 0.5, 0.5, 0.5]);
+
+        let back: Vec<f32> = clips["back"].frames.iter().map(|x| x.duration).collect();
+        assert_eq!(back, [0.3, 0.2, 0.1]);
+        assert_eq!(clips["bounce"].mode, PlayMode::PingPong);
+        assert_eq!(clips["bounce"].frames[1].uv_rect, [0.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn aseprite_without_tags_has_a_default_clip() {
+        let dir = TempDir::new("aseprite-hash");
+        let path = dir.write("sheet.json", r#"{"frames": {
+            "a.png": {"frame": {"x": 0, "y": 0, "w": 8, "h": 8}, "duration": 50},
+            "b.png": {"frame": {"x": 8, "y": 0, "w": 8, "h": 8}, "duration": 50}
+        }, "meta": {"size": {"w": 16, "h": 8}}}"#);
+        let clips = load_aseprite(&path, "sheet").unwrap();
+        assert_eq!(clips.len(), 1);
+        let clip = &clips["default"];
+        assert_eq!(clip.mode, PlayMode::Loop);
+        // Hash exports keep their frame order
+        assert_eq!(clip.frames[1].uv_rect, [0.5, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn aseprite_errors() {
+        let dir = TempDir::new("aseprite-errors");
+        let path = dir.write("range.json", format!(r#"{{"frames": {}, "meta": {{"size": {{"w": 32, "h": 32}},
+            "frameTags": [{{"name": "walk", "from": 1, "to": 3}}]}}}}"#, FRAMES));
+        let error = load_aseprite(&path, "sheet").unwrap_err();
+        assert!(error.contains("out of range"), "{}", error);
+
+        let path = dir.write("invalid.json", r#"{"frames": []}"#);
+        let error = load_aseprite(&path, "sheet").unwrap_err();
+        assert!(error.contains("Invalid Aseprite sheet"), "{}", error);
+
+        assert!(load_aseprite("/nonexistent/rgms-sheet.json", "sheet").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::atlas::{AtlasImage, AtlasOptions};
+use crate::hot_reload::ShaderFiles;
+use crate::sampler::SamplerDesc;
+use crate::shader::{ShaderSource, ShaderStage};
+use crate::texture::{self, TextureImage};
+
+/// What a worker reads and decodes, everything that doesn't need the GL context
+pub enum AssetRequest {
+    Texture { path: String, srgb: bool, sampler: SamplerDesc },
+    Shader { files: ShaderFiles, defines: Vec<(String, String)> },
+    Atlas { files: Vec<(String, String)>, options: AtlasOptions },
+}
+
+/// Decoded asset waiting for its GL upload on the main thread
+pub enum LoadedAsset {
+    Texture { image: TextureImage, path: String, srgb: bool, sampler: SamplerDesc },
+    Shader { stages: Vec<(ShaderStage, ShaderSource)>, files: ShaderFiles, defines: Vec<(String, String)> },
+    Atlas { image: AtlasImage, files: Vec<(String, String)>, options: AtlasOptions },
+}
+
+impl AssetRequest {
+    fn decode(self) -> Result<LoadedAsset, String> {
+        match self {
+            AssetRequest::Texture { path, srgb, sampler } => {
+                let image = texture::open_image(&path, srgb)?;
+                Ok(LoadedAsset::Texture { image, path, srgb, sampler })
+            }
+            AssetRequest::Shader { files, defines } => {
+                let stages = files.load(&defines).map_err(|e| e.to_string())?;
+                Ok(LoadedAsset::Shader { stages, files, defines })
+            }
+            AssetRequest::Atlas { files, options } => {
+                let image = AtlasImage::from_files(&files, &options)?;
+                Ok(LoadedAsset::Atlas { image, files, options })
+            }
+        }
+    }
+}
+
+/// A panicking decoder still has to answer, `next(true)` would wait for it forever
+fn decode_catching(decode: impl FnOnce() -> Result<LoadedAsset, String>) -> Result<LoadedAsset, String> {
+    catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|_| Err("Decoding panicked".to_string()))
+}
+
+struct Job {
+    key: String,
+    request: AssetRequest,
+}
+
+pub struct Loaded {
+    pub key: String,
+    pub result: Result<LoadedAsset, String>,
+}
+
+/// Decodes assets on a pool of worker threads, results are collected on the main thread
+pub struct AssetLoader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Loaded>,
+    workers: Vec<JoinHandle<()>>,
+    queued: usize,
+    finished: usize,
+}
+
+impl AssetLoader {
+    pub fn new(worker_count: usize) -> Self {
+        let (jobs, job_receiver) = channel::<Job>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..worker_count.max(1))
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset-loader-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before decoding so the other workers can take jobs
+                        let job = match jobs.lock().unwrap().recv() {
+                            Ok(x) => x,
+                            Err(_) => return,
+                        };
+                        let loaded = Loaded { key: job.key, result: decode_catching(|| job.request.decode()) };
+                        if results.send(loaded).is_err() {
+                            return;
+                        }
+                    })
+                    .expect("Failed to spawn asset loader thread")
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            queued: 0,
+            finished: 0,
+        }
+    }
+
+    pub fn load(&mut self, key: &str, request: AssetRequest) {
+        // A new batch after everything finished starts the progress over
+        if self.is_idle() {
+            self.queued = 0;
+            self.finished = 0;
+        }
+        self.queued += 1;
+        let job = Job { key: key.to_string(), request };
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("Asset loader threads have stopped");
+        }
+    }
+
+    /// Next decoded asset, `wait` blocks until one is ready. None once nothing is pending
+    pub fn next(&mut self, wait: bool) -> Option<Loaded> {
+        if self.is_idle() {
+            return None;
+        }
+        let loaded = if wait {
+            self.results.recv().ok()?
+        } else {
+            self.results.try_recv().ok()?
+        };
+        self.finished += 1;
+        Some(loaded)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.finished == self.queued
+    }
+    /// (finished, queued) since the loader was last idle
+    pub fn progress(&self) -> (usize, usize) {
+        (self.finished, self.queued)
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers once they finish their current job
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::TempDir;
+
+    fn texture(path: String) -> AssetRequest {
+        AssetRequest::Texture { path, srgb: false, sampler: SamplerDesc::default() }
+    }
+
+    #[test]
+    fn progress_counts_the_current_batch() {
+        let dir = TempDir::new("loader-progress");
+        let mut loader = AssetLoader::new(2);
+        assert!(loader.is_idle());
+        assert!(loader.next(true).is_none());
+
+        for i in 0..3 {
+            loader.load(&format!("missing{}", i), texture(dir.file(&format!("missing{}.png", i))));
+        }
+        assert_eq!(loader.progress().1, 3);
+        for _ in 0..3 {
+            assert!(loader.next(true).unwrap().result.is_err());
+        }
+        assert_eq!(loader.progress(), (3, 3));
+        assert!(loader.is_idle());
+        assert!(loader.next(true).is_none());
+
+        // Finished batches are forgotten by the next one
+        loader.load("again", texture(dir.file("again.png")));
+        assert_eq!(loader.progress(), (0, 1));
+        loader.next(true).unwrap();
+        assert_eq!(loader.progress(), (1, 1));
+    }
+
+    #[test]
+    fn single_worker_keeps_queue_order() {
+        let dir = TempDir::new("loader-order");
+        let image_path = dir.file("pixel.png");
+        image::RgbaImage::new(2, 1).save(&image_path).unwrap();
+
+        let mut loader = AssetLoader::new(1);
+        loader.load("a", texture(dir.file("missing.png")));
+        loader.load("b", texture(image_path.clone()));
+        loader.load("c", texture(dir.file("missing.png")));
+        loader.load("d", texture(image_path.clone()));
+
+        let results: Vec<_> = std::iter::from_fn(|| loader.next(true)).collect();
+        let keys: Vec<_> = results.iter().map(|x| x.key.as_str()).collect();
+        assert_eq!(keys, ["a", "b", "c", "d"]);
+        let ok: Vec<_> = results.iter().map(|x| x.result.is_ok()).collect();
+        assert_eq!(ok, [false, true, false, true]);
+        match &results[1].result {
+            Ok(LoadedAsset::Texture { image, .. }) => assert_eq!((image.width, image.height), (2, 1)),
+            _ => panic!("expected a decoded texture"),
+        }
+    }
+
+    #[test]
+    fn panicking_decode_becomes_an_error() {
+        let result = decode_catching(|| panic!("broken decoder"));
+        assert_eq!(result.err().unwrap(), "Decoding panicked");
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+use image::{imageops, RgbaImage};
+
+use crate::sampler::SamplerDesc;
+use crate::texture::{self, Texture, TextureFormat, TextureImage, TexturePixels};
+
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasRegion {
+    /// Pixel rectangle in the atlas, origin at the top left
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// offset.xy, size.xy in texture coordinates, what `SpriteInstance::uv_rect` and the
+    /// `sprite_offset`/`sprite_size` uniforms expect
+    pub uv_rect: [f32; 4],
+}
+
+impl AtlasRegion {
+    /// Maps a rectangle given in the region's own [0, 1] coordinates into the atlas
+    pub fn sub_rect(&self, uv: [f32; 4]) -> [f32; 4] {
+        sub_rect(self.uv_rect, uv)
+    }
+}
+
+/// Maps `uv`, given in `rect`'s own [0, 1] coordinates, into the space `rect` is in
+pub fn sub_rect(rect: [f32; 4], uv: [f32; 4]) -> [f32; 4] {
+    [rect[0] + uv[0] * rect[2], rect[1] + uv[1] * rect[3], uv[2] * rect[2], uv[3] * rect[3]]
+}
+
+/// Name of a region listed without one, the image's file stem
+pub fn region_name(path: &str) -> Option<String> {
+    std::path::Path::new(path).file_stem().map(|x| x.to_string_lossy().to_string())
+}
+
+#[derive(Clone, PartialEq)]
+pub struct AtlasOptions {
+    /// Border around every image, filled with its edge pixels so filtering doesn't bleed neighbours in
+    pub padding: u32,
+    pub max_size: u32,
+    pub srgb: bool,
+    pub sampler: SamplerDesc,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> Self {
+        Self {
+            padding: 2,
+            max_size: 4096,
+            srgb: false,
+            // Mipmaps would blend neighbouring regions together
+            sampler: SamplerDesc::clamped(),
+        }
+    }
+}
+
+/// Packed atlas pixels, decoding and packing don't need a GL context so they can run on a worker
+pub struct AtlasImage {
+    image: TextureImage,
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl AtlasImage {
+    /// `files` are (region name, path) pairs
+    pub fn from_files(files: &[(String, String)], options: &AtlasOptions) -> Result<AtlasImage, String> {
+        let mut images = Vec::new();
+        for (name, path) in files {
+            let image = texture::decode_image(path)?;
+            images.push((name.clone(), image.to_rgba8()));
+        }
+        Self::pack(images, options)
+    }
+
+    pub fn pack(images: Vec<(String, RgbaImage)>, options: &AtlasOptions) -> Result<AtlasImage, String> {
+        // The padding is filled from the image's edge pixels, an empty image has none
+        if let Some((name, _)) = images.iter().find(|(_, x)| x.width() == 0 || x.height() == 0) {
+            return Err(format!("Atlas image \"{}\" is empty", name));
+        }
+        let padding = options.padding;
+        let sizes: Vec<(u32, u32)> = images.iter()
+            .map(|(_, x)| (x.width() + padding * 2, x.height() + padding * 2))
+            .collect();
+        let (width, height, positions) = pack_rects(&sizes, options.max_size)
+            .ok_or_else(|| format!("{} image(s) don't fit in a {}x{} atlas", images.len(), options.max_size, options.max_size))?;
+
+        let mut atlas = RgbaImage::new(width, height);
+        let mut regions = HashMap::new();
+        for ((name, image), (x, y)) in images.iter().zip(positions) {
+            let (w, h) = (image.width(), image.height());
+            // Copies the image and extrudes its edges into the padding in one pass
+            for py in 0..h + padding * 2 {
+                for px in 0..w + padding * 2 {
+                    let sx = px.saturating_sub(padding).min(w - 1);
+                    let sy = py.saturating_sub(padding).min(h - 1);
+                    atlas.put_pixel(x + px, y + py, *image.get_pixel(sx, sy));
+                }
+            }
+            let (x, y) = (x + padding, y + padding);
+            regions.insert(name.clone(), AtlasRegion {
+                x,
+                y,
+                width: w,
+                height: h,
+                // The atlas is flipped on upload, so v is measured from the bottom
+                uv_rect: [
+                    x as f32 / width as f32,
+                    (height - y - h) as f32 / height as f32,
+                    w as f32 / width as f32,
+                    h as f32 / height as f32,
+                ],
+            });
+        }
+
+        let image = TextureImage {
+            format: if options.srgb { TextureFormat::Srgb8Alpha8 } else { TextureFormat::Rgba8 },
+            width,
+            height,
+            pixels: TexturePixels::U8(imageops::flip_vertical(&atlas).into_raw()),
+        };
+        Ok(AtlasImage { image, regions })
+    }
+}
+
+pub struct Atlas {
+    texture: Texture,
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+    pub fn from_files(files: &[(String, String)], options: &AtlasOptions) -> Result<Atlas, String> {
+        Ok(Self::upload(AtlasImage::from_files(files, options)?, &options.sampler))
+    }
+
+    pub fn upload(image: AtlasImage, sampler: &SamplerDesc) -> Atlas {
+        let mut texture = Texture::create_new(gl::TEXTURE_2D);
+        texture.set_sampler(sampler);
+        texture.set_image(&image.image);
+        Atlas { texture, regions: image.regions }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.get(name)
+    }
+}
+
+/// Atlas width, height and the top left corner of every rectangle
+pub type PackedRects = (u32, u32, Vec<(u32, u32)>);
+
+/// Packs the rectangles into the smallest power of two size that fits, growing the shorter side first.
+/// Returns the size and the top left corner of every rectangle, in input order
+pub fn pack_rects(sizes: &[(u32, u32)], max_size: u32) -> Option<PackedRects> {
+    if sizes.iter().any(|&(w, h)| w == 0 || h == 0 || w > max_size || h > max_size) {
+        return None;
+    }
+    // Tallest first packs a skyline tightest
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse((sizes[i].1, sizes[i].0)));
+
+    let (mut width, mut height) = (64.min(max_size), 64.min(max_size));
+    loop {
+        let mut skyline = Skyline::new(width, height);
+        let mut positions = vec![(0, 0); sizes.len()];
+        let packed = order.iter().all(|&i| match skyline.insert(sizes[i].0, sizes[i].1) {
+            Some(x) => {
+                positions[i] = x;
+                true
+            }
+            None => false,
+        });
+        if packed {
+            return Some((width, height, positions));
+        }
+        if width <= height && width < max_size {
+            width *= 2;
+        } else if height < max_size {
+            height *= 2;
+        } else {
+            return None;
+        }
+    }
+}
+
+struct SkylineNode {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// Bottom-left skyline packer, the nodes always cover the full width
+struct Skyline {
+    width: u32,
+    height: u32,
+    nodes: Vec<SkylineNode>,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            nodes: vec![SkylineNode { x: 0, y: 0, width }],
+        }
+    }
+
+    /// Lowest y a rectangle starting at node `index` can be placed at
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.nodes[index].x;
+        if x + width > self.width {
+            return None;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for node in &self.nodes[index..] {
+            if covered >= width {
+                break;
+            }
+            y = y.max(node.y);
+            covered += node.width;
+        }
+        if y + height > self.height {
+            return None;
+        }
+        Some(y)
+    }
+
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32, u32)> = None;
+        for index in 0..self.nodes.len() {
+            if let Some(y) = self.fit(index, width, height) {
+                let x = self.nodes[index].x;
+                if best.is_none_or(|(_, bx, by)| (y, x) < (by, bx)) {
+                    best = Some((index, x, y));
+                }
+            }
+        }
+        let (index, x, y) = best?;
+
+        self.nodes.insert(index, SkylineNode { x, y: y + height, width });
+        // Cut the nodes now under the new one
+        let end = x + width;
+        let i = index + 1;
+        while i < self.nodes.len() && self.nodes[i].x < end {
+            let node_end = self.nodes[i].x + self.nodes[i].width;
+            if node_end <= end {
+                self.nodes.remove(i);
+            } else {
+                self.nodes[i].width = node_end - end;
+                self.nodes[i].x = end;
+                break;
+            }
+        }
+        // Merge neighbours at the same height
+        let mut i = 0;
+        while i + 1 < self.nodes.len() {
+            if self.nodes[i].y == self.nodes[i + 1].y {
+                self.nodes[i].width += self.nodes[i + 1].width;
+                self.nodes.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+        Some((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn assert_valid(sizes: &[(u32, u32)], (width, height, positions): &PackedRects) {
+        assert!(width.is_power_of_two() && height.is_power_of_two());
+        assert_eq!(positions.len(), sizes.len());
+        let rects: Vec<_> = positions.iter().zip(sizes).map(|(&(x, y), &(w, h))| (x, y, w, h)).collect();
+        for (i, &(x, y, w, h)) in rects.iter().enumerate() {
+            assert!(x + w <= *width && y + h <= *height, "rect {} is outside the atlas", i);
+            for &(ox, oy, ow, oh) in &rects[i + 1..] {
+                assert!(x + w <= ox || ox + ow <= x || y + h <= oy || oy + oh <= y, "rects overlap");
+            }
+        }
+    }
+
+    #[test]
+    fn pack_rects_keeps_input_order_without_overlaps() {
+        let sizes = [(10, 5), (30, 40), (64, 8), (7, 7), (20, 33), (1, 1)];
+        let packed = pack_rects(&sizes, 1024).unwrap();
+        assert_valid(&sizes, &packed);
+        // Tallest first, so the 40 high rect starts the skyline
+        assert_eq!(packed.2[1], (0, 0));
+    }
+
+    #[test]
+    fn pack_rects_grows_the_shorter_side_first() {
+        assert_eq!(pack_rects(&[(64, 64)], 1024).unwrap().0, 64);
+        let (width, height, _) = pack_rects(&[(64, 64), (64, 64)], 1024).unwrap();
+        assert_eq!((width, height), (128, 64));
+        let (width, height, _) = pack_rects(&[(64, 64); 3], 1024).unwrap();
+        assert_eq!((width, height), (128, 128));
+
+        let sizes = vec![(16, 16); 100];
+        let packed = pack_rects(&sizes, 1024).unwrap();
+        assert_eq!((packed.0, packed.1), (256, 128));
+        assert_valid(&sizes, &packed);
+    }
+
+    #[test]
+    fn pack_rects_rejects_what_cant_fit() {
+        assert!(pack_rects(&[(0, 4)], 1024).is_none());
+        assert!(pack_rects(&[(4, 0)], 1024).is_none());
+        assert!(pack_rects(&[(65, 4)], 64).is_none());
+        assert!(pack_rects(&[(64, 64), (1, 1)], 64).is_none());
+        // Small limits aren't raised to the 64 starting size
+        assert_eq!(pack_rects(&[(8, 8)], 16).map(|x| (x.0, x.1)), Some((16, 16)));
+        assert_eq!(pack_rects(&[], 1024).map(|x| x.2.len()), Some(0));
+    }
+
+    #[test]
+    fn pack_rejects_empty_images() {
+        let images = vec![("ok".to_string(), RgbaImage::new(2, 2)), ("empty".to_string(), RgbaImage::new(0, 0))];
+        let error = AtlasImage::pack(images, &AtlasOptions::default()).err().unwrap();
+        assert!(error.contains("\"empty\""), "{}", error);
+    }
+
+    #[test]
+    fn pack_extrudes_edges_into_the_padding() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+        let options = AtlasOptions { padding: 1, max_size: 64, ..AtlasOptions::default() };
+        let atlas = AtlasImage::pack(vec![("a".to_string(), image)], &options).unwrap();
+
+        let region = atlas.regions["a"];
+        assert_eq!((region.x, region.y, region.width, region.height), (1, 1, 2, 1));
+        assert_eq!(region.uv_rect, [1.0 / 64.0, 62.0 / 64.0, 2.0 / 64.0, 1.0 / 64.0]);
+        let TexturePixels::U8(pixels) = &atlas.image.pixels else {
+            panic!("expected 8 bit pixels");
+        };
+        // Stored flipped, row 0 of the 4x3 padded block is the last row of the atlas
+        let pixel = |x: u32, y: u32| {
+            let start = (((63 - y) * 64 + x) * 4) as usize;
+            &pixels[start..start + 4]
+        };
+        for y in 0..3 {
+            assert_eq!(pixel(0, y), [255, 0, 0, 255]);
+            assert_eq!(pixel(1, y), [255, 0, 0, 255]);
+            assert_eq!(pixel(2, y), [0, 0, 255, 255]);
+            assert_eq!(pixel(3, y), [0, 0, 255, 255]);
+        }
+        assert_eq!(pixel(4, 0), [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use glfw::Context;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use rgms::shader::{Shader, ShaderSource, ShaderStage};
+use rgms::texture::{self, TextureImage};
+use rgms::vfs;
+
+const USAGE: &str = "Usage: rgms-pack <directory> [output] [--prefix path] [--no-compile]
+
+Packs every file under <directory> into [output] (resources.pack by default), which the engine
+mounts on startup. Files are stored under --prefix, the directory path by default, so
+`rgms-pack ./resources` keeps paths like \"./resources/image.png\" working.
+Images are stored as baked textures that load without decoding. Shaders (.glsl files with
+`#pragma stage` sections and .vert/.frag pairs) are preprocessed and compiled, --no-compile
+skips compiling when no OpenGL context is available";
+
+struct PackOptions {
+    directory: String,
+    output: String,
+    prefix: Option<String>,
+    compile: bool,
+}
+
+fn parse_args() -> Option<PackOptions> {
+    let mut paths = Vec::new();
+    let mut prefix = None;
+    let mut compile = true;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prefix" => prefix = Some(args.next()?),
+            "--no-compile" => compile = false,
+            "--help" | "-h" => return None,
+            x if x.starts_with("--") => {
+                println!("Unknown argument \"{}\"", x);
+                return None;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let mut paths = paths.into_iter();
+    let directory = paths.next()?;
+    let output = paths.next().unwrap_or_else(|| "resources.pack".to_string());
+    if paths.next().is_some() {
+        return None;
+    }
+    Some(PackOptions { directory, output, prefix, compile })
+}
+
+/// Virtual paths of the files under `directory`
+fn collect_files(directory: &Path, virtual_directory: &str, files: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = if virtual_directory.is_empty() { name } else { format!("{}/{}", virtual_directory, name) };
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn extension(path: &str) -> &str {
+    Path::new(path).extension().and_then(|x| x.to_str()).unwrap_or("")
+}
+
+fn directory(path: &str) -> &str {
+    path.rsplit_once('/').map(|(x, _)| x).unwrap_or("")
+}
+
+/// Path the program is named after and its stages
+type ShaderProgram = (String, Result<Vec<(ShaderStage, ShaderSource)>, String>);
+
+/// Programs the shader files make up. A .vert is paired with the .frag of the same name,
+/// or the only .frag in its directory. Other .glsl files are includes, checked where they're included
+fn shader_programs(files: &[String]) -> Vec<ShaderProgram> {
+    let mut programs = Vec::new();
+    for path in files {
+        match extension(path) {
+            "glsl" => {
+                let is_program = vfs::read_to_string(path)
+                    .map(|x| x.lines().any(|line| line.trim().starts_with("#pragma stage")))
+                    .unwrap_or(true);
+                if is_program {
+                    programs.push((path.clone(), ShaderSource::stages_from_file(path, &[]).map_err(|e| e.to_string())));
+                }
+            }
+            "vert" => {
+                let same_name = format!("{}.frag", path.strip_suffix(".vert").unwrap());
+                let fragments: Vec<&String> = files.iter()
+                    .filter(|x| extension(x) == "frag" && directory(x) == directory(path))
+                    .collect();
+                let fragment = match fragments.iter().find(|x| **x == &same_name) {
+                    Some(x) => Ok(*x),
+                    None if fragments.len() == 1 => Ok(fragments[0]),
+                    None => Err(format!("no matching .frag for \"{}\", expected \"{}\" or a single .frag in the directory", path, same_name)),
+                };
+                let stages = fragment.and_then(|fragment| Ok(vec![
+                    (ShaderStage::Vertex, ShaderSource::from_file(path, &[]).map_err(|e| e.to_string())?),
+                    (ShaderStage::Fragment, ShaderSource::from_file(fragment, &[]).map_err(|e| e.to_string())?),
+                ]));
+                programs.push((path.clone(), stages));
+            }
+            _ => {}
+        }
+    }
+    programs
+}
+
+fn error_callback(err: glfw::Error, description: String) {
+    println!("GLFW error {:?}: {:?}", err, description);
+}
+
+/// Hidden window whose context the shaders are compiled in
+fn create_context() -> Option<(glfw::Glfw, glfw::PWindow)> {
+    let mut glfw = glfw::init(error_callback).ok()?;
+    glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
+    glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+    glfw.window_hint(glfw::WindowHint::Visible(false));
+    let (mut window, _) = glfw.create_window(1, 1, "rgms-pack", glfw::WindowMode::Windowed)?;
+    window.make_current();
+    gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
+    Some((glfw, window))
+}
+
+/// Contents stored for `path`, images are baked
+fn pack_file(path: &str) -> Result<(Vec<u8>, bool), String> {
+    if image::ImageFormat::from_path(path).is_ok() {
+        let image = texture::decode_image(path)?;
+        return Ok((TextureImage::from_image(image.flipv(), false).to_baked(), true));
+    }
+    vfs::read(path).map(|x| (x, false)).map_err(|e| format!("Failed to read \"{}\": {}", path, e))
+}
+
+fn main() {
+    let Some(options) = parse_args() else {
+        println!("{}", USAGE);
+        std::process::exit(2);
+    };
+    let prefix = match options.prefix.as_deref().map_or_else(|| vfs::normalize(&options.directory), vfs::normalize) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Invalid prefix: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = vfs::mount(&options.directory, &prefix) {
+        println!("Failed to open \"{}\": {}", options.directory, e);
+        std::process::exit(1);
+    }
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(Path::new(&options.directory), &prefix, &mut files) {
+        println!("Failed to list \"{}\": {}", options.directory, e);
+        std::process::exit(1);
+    }
+    // Sorted so packs of the same files are identical
+    files.sort();
+
+    let mut errors = Vec::new();
+    let context = if options.compile { create_context() } else { None };
+    if options.compile && context.is_none() {
+        errors.push("Failed to create an OpenGL context to compile shaders, use --no-compile to only preprocess them".to_string());
+    }
+    for (path, stages) in shader_programs(&files) {
+        let result = stages.and_then(|stages| {
+            if context.is_some() {
+                let stages: Vec<(ShaderStage, &ShaderSource)> = stages.iter().map(|(stage, source)| (*stage, source)).collect();
+                Shader::from_stages(&stages).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            errors.push(format!("Shader \"{}\": {}", path, e));
+        }
+    }
+    drop(context);
+
+    // Written next to the output and renamed over it once complete, so a failed run keeps the previous pack
+    let temp_output = format!("{}.tmp", options.output);
+    let result = File::create(&temp_output).map_err(|e| e.to_string()).and_then(|file| {
+        let mut writer = ZipWriter::new(file);
+        let zip_options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut baked = 0;
+        for path in &files {
+            let data = match pack_file(path) {
+                Ok((data, is_baked)) => {
+                    baked += is_baked as usize;
+                    data
+                }
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            writer.start_file(path.as_str(), zip_options).map_err(|e| e.to_string())?;
+            writer.write_all(&data).map_err(|e| e.to_string())?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+        Ok(baked)
+    });
+    let result = match result {
+        Ok(baked) if errors.is_empty() => std::fs::rename(&temp_output, &options.output).map(|_| baked).map_err(|e| e.to_string()),
+        x => x,
+    };
+    match result {
+        Ok(baked) if errors.is_empty() => {
+            println!("Packed {} file(s), {} baked image(s), into \"{}\"", files.len(), baked, options.output);
+        }
+        result => {
+            if let Err(e) = result {
+                errors.push(format!("Failed to write \"{}\": {}", options.output, e));
+            }
+            for e in &errors {
+                println!("{}", e);
+            }
+            // A partial pack would silently miss files at runtime, the previous one is left in place
+            let _ = std::fs::remove_file(&temp_output);
+            std::process::exit(1);
+        }
+    }
+}
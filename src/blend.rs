@@ -0,0 +1,44 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlendMode {
+    /// Blending disabled
+    Opaque,
+    /// Straight alpha
+    Alpha,
+    /// Color already multiplied by alpha
+    Premultiplied,
+    Additive,
+    Multiply,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        match name {
+            "opaque" => Some(BlendMode::Opaque),
+            "alpha" => Some(BlendMode::Alpha),
+            "premultiplied" => Some(BlendMode::Premultiplied),
+            "additive" => Some(BlendMode::Additive),
+            "multiply" => Some(BlendMode::Multiply),
+            _ => None,
+        }
+    }
+    pub fn is_blended(self) -> bool {
+        self != BlendMode::Opaque
+    }
+    pub fn apply(self) {
+        unsafe {
+            match self {
+                BlendMode::Opaque => {
+                    gl::Disable(gl::BLEND);
+                    return;
+                }
+                // Alpha channel keeps "over" compositing so captures of the framebuffer stay usable
+                BlendMode::Alpha => gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Premultiplied => gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+                BlendMode::Multiply => gl::BlendFunc(gl::DST_COLOR, gl::ONE_MINUS_SRC_ALPHA),
+            }
+            gl::BlendEquation(gl::FUNC_ADD);
+            gl::Enable(gl::BLEND);
+        }
+    }
+}
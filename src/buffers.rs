@@ -1,5 +1,7 @@
 use gl::types::GLenum;
 
+use crate::texture::Texture;
+
 pub struct BufferObject {
     buffer: u32,
     buffer_type: GLenum,
@@ -10,6 +12,9 @@ pub struct VertexArrayObject {
 pub struct FramebufferObject {
     buffer: u32,
 }
+pub struct RenderbufferObject {
+    buffer: u32,
+}
 
 impl BufferObject {
     pub fn new(buffer_type: GLenum) -> BufferObject {
@@ -36,7 +41,7 @@ impl BufferObject {
             gl::BindBuffer(self.buffer_type, self.buffer);
             gl::BufferData(
                 self.buffer_type,
-                (data.len() * std::mem::size_of::<T>()) as isize,
+                std::mem::size_of_val(data) as isize,
                 data.as_ptr() as *const _,
                 usage
             );
@@ -65,6 +70,11 @@ impl BufferObject {
         }
     }
 }
+impl Default for VertexArrayObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl VertexArrayObject {
     pub fn new() -> VertexArrayObject {
         // Initialize with buffer = 0
@@ -91,10 +101,16 @@ impl VertexArrayObject {
     }
 }
 
+impl Default for FramebufferObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FramebufferObject {
-    pub fn new() -> VertexArrayObject {
+    pub fn new() -> FramebufferObject {
         // Initialize with buffer = 0
-        VertexArrayObject {
+        FramebufferObject {
             buffer: 0
         }
     }
@@ -109,6 +125,36 @@ impl FramebufferObject {
     pub fn bind(&self) {
         unsafe {gl::BindFramebuffer(gl::FRAMEBUFFER,self.buffer) };
     }
+    pub fn unbind() {
+        unsafe {gl::BindFramebuffer(gl::FRAMEBUFFER,0) };
+    }
+    pub fn attach_texture(&self, attachment: GLenum, texture: &Texture) {
+        self.bind();
+        unsafe {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, texture.texture_type(), texture.handle(), 0);
+        }
+    }
+    pub fn attach_renderbuffer(&self, attachment: GLenum, renderbuffer: &RenderbufferObject) {
+        self.bind();
+        unsafe {
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer.buffer);
+        }
+    }
+    pub fn check_status(&self) -> Result<(), String> {
+        self.bind();
+        let status = unsafe { gl::CheckFramebufferStatus(gl::FRAMEBUFFER) };
+        match status {
+            gl::FRAMEBUFFER_COMPLETE => Ok(()),
+            gl::FRAMEBUFFER_UNDEFINED => Err("FRAMEBUFFER_UNDEFINED".to_string()),
+            gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => Err("FRAMEBUFFER_INCOMPLETE_ATTACHMENT".to_string()),
+            gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => Err("FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT".to_string()),
+            gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => Err("FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER".to_string()),
+            gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => Err("FRAMEBUFFER_INCOMPLETE_READ_BUFFER".to_string()),
+            gl::FRAMEBUFFER_UNSUPPORTED => Err("FRAMEBUFFER_UNSUPPORTED".to_string()),
+            gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => Err("FRAMEBUFFER_INCOMPLETE_MULTISAMPLE".to_string()),
+            x => Err(format!("Unknown framebuffer status 0x{:X}", x)),
+        }
+    }
     fn delete(&mut self) {
         if self.buffer != 0 {
             unsafe { gl::DeleteFramebuffers(1, &self.buffer) }; // Changed from DeleteVertexArrays to DeleteBuffers
@@ -117,6 +163,42 @@ impl FramebufferObject {
     }
 }
 
+impl Default for RenderbufferObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderbufferObject {
+    pub fn new() -> RenderbufferObject {
+        RenderbufferObject {
+            buffer: 0
+        }
+    }
+
+    pub fn create(&mut self) {
+        self.delete();
+        unsafe {
+            gl::GenRenderbuffers(1, &mut self.buffer);
+        }
+    }
+
+    pub fn bind(&self) {
+        unsafe {gl::BindRenderbuffer(gl::RENDERBUFFER,self.buffer) };
+    }
+    pub fn set_storage(&self, internal_format: GLenum, width: i32, height: i32) {
+        self.bind();
+        unsafe {
+            gl::RenderbufferStorage(gl::RENDERBUFFER, internal_format, width, height);
+        }
+    }
+    fn delete(&mut self) {
+        if self.buffer != 0 {
+            unsafe { gl::DeleteRenderbuffers(1, &self.buffer) };
+            self.buffer = 0;
+        }
+    }
+}
 
 impl Drop for BufferObject {
     fn drop(&mut self) {
@@ -132,4 +214,9 @@ impl Drop for FramebufferObject {
     fn drop(&mut self) {
         self.delete();
     }
+}
+impl Drop for RenderbufferObject {
+    fn drop(&mut self) {
+        self.delete();
+    }
 }
\ No newline at end of file
@@ -24,6 +24,8 @@ impl Camera {
     pub const Z_NEAR_PLANE: f32 = 0.01;
     pub const Z_FAR_PLANE: f32 = 30.0;
 
+    // Creates its uniform buffer, so it needs a GL context unlike a Default
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let mut camera = Camera {
             position: Vec2::new(0.0, 0.0),
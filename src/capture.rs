@@ -0,0 +1,126 @@
+use image::{imageops, Rgba, RgbaImage};
+
+pub struct ImageDiff {
+    pub mismatched_pixels: u32,
+    pub max_channel_delta: u8,
+    pub diff: RgbaImage,
+}
+
+impl ImageDiff {
+    pub fn matches(&self) -> bool {
+        self.mismatched_pixels == 0
+    }
+}
+
+/// Reads the current viewport of the bound read framebuffer
+pub fn read_framebuffer() -> RgbaImage {
+    let mut viewport = [0; 4];
+    unsafe { gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr()) };
+    let (width, height) = (viewport[2].max(0) as u32, viewport[3].max(0) as u32);
+
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(viewport[0], viewport[1], width as i32, height as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+    }
+    let image = RgbaImage::from_raw(width, height, pixels).unwrap();
+    // OpenGL rows start at the bottom
+    imageops::flip_vertical(&image)
+}
+
+pub fn capture_frame(path: &str) -> Result<(), String> {
+    read_framebuffer()
+        .save(path)
+        .map_err(|e| format!("Failed to save capture \"{}\": {}", path, e))
+}
+
+/// Per-channel comparison, a pixel mismatches when any channel differs by more than `tolerance`
+pub fn compare_images(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Result<ImageDiff, String> {
+    if expected.dimensions() != actual.dimensions() {
+        return Err(format!("Image size mismatch: expected {:?}, actual {:?}", expected.dimensions(), actual.dimensions()));
+    }
+    let mut result = ImageDiff {
+        mismatched_pixels: 0,
+        max_channel_delta: 0,
+        diff: RgbaImage::new(expected.width(), expected.height()),
+    };
+    for ((e, a), d) in expected.pixels().zip(actual.pixels()).zip(result.diff.pixels_mut()) {
+        let delta = e.0.iter().zip(a.0.iter()).map(|(x, y)| x.abs_diff(*y)).max().unwrap_or(0);
+        result.max_channel_delta = result.max_channel_delta.max(delta);
+        *d = if delta > tolerance {
+            result.mismatched_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // Matching pixels are kept as a faded grayscale of the expected image for context
+            let luma = ((e.0[0] as u32 + e.0[1] as u32 + e.0[2] as u32) / 3 / 4) as u8;
+            Rgba([luma, luma, luma, 255])
+        };
+    }
+    Ok(result)
+}
+
+pub fn compare_files(expected_path: &str, actual_path: &str, tolerance: u8, diff_path: Option<&str>) -> Result<ImageDiff, String> {
+    let open = |path: &str| {
+        image::open(path)
+            .map(|x| x.to_rgba8())
+            .map_err(|e| format!("Failed to open image \"{}\": {}", path, e))
+    };
+    let diff = compare_images(&open(expected_path)?, &open(actual_path)?, tolerance)?;
+    if let Some(path) = diff_path {
+        diff.diff.save(path).map_err(|e| format!("Failed to save diff \"{}\": {}", path, e))?;
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+    }
+
+    #[test]
+    fn tolerance_is_inclusive() {
+        let expected = filled(2, 2, [100, 100, 100, 255]);
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 0, Rgba([104, 100, 100, 255]));
+
+        let diff = compare_images(&expected, &actual, 4).unwrap();
+        assert!(diff.matches());
+        assert_eq!(diff.max_channel_delta, 4);
+
+        let diff = compare_images(&expected, &actual, 3).unwrap();
+        assert!(!diff.matches());
+        assert_eq!(diff.mismatched_pixels, 1);
+        assert_eq!(diff.max_channel_delta, 4);
+    }
+
+    #[test]
+    fn alpha_differences_count() {
+        let expected = filled(1, 1, [0, 0, 0, 255]);
+        let actual = filled(1, 1, [0, 0, 0, 0]);
+        let diff = compare_images(&expected, &actual, 254).unwrap();
+        assert_eq!(diff.mismatched_pixels, 1);
+        assert_eq!(diff.max_channel_delta, 255);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let error = compare_images(&filled(2, 2, [0; 4]), &filled(2, 3, [0; 4]), 255).err().unwrap();
+        assert!(error.contains("(2, 2)") && error.contains("(2, 3)"), "{}", error);
+    }
+
+    #[test]
+    fn diff_marks_mismatches_red_over_faded_expected() {
+        let expected = filled(2, 1, [120, 60, 0, 255]);
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+
+        let diff = compare_images(&expected, &actual, 0).unwrap();
+        assert_eq!(diff.diff.dimensions(), (2, 1));
+        assert_eq!(diff.diff.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        // (120 + 60 + 0) / 3 / 4
+        assert_eq!(diff.diff.get_pixel(1, 0), &Rgba([15, 15, 15, 255]));
+    }
+}
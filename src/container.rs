@@ -1,32 +1,501 @@
-use std::{cell::RefCell, rc::Rc};
+use std::rc::Rc;
 
-use crate::{resource_manager::ResourceManager, transform::Transform2D};
+use crate::{animation::SharedAnimator, ecs::{Entity, World}, jobs::JobPool, resource_manager::ResourceManager, sprite_batch::{Sprite, SpriteInstance}, transform::{self, Transform2D}};
 
+pub type ContainerId = Entity;
+
+const IDENTITY: [f32; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Place of an entity in the scene hierarchy
 pub struct Container2D {
-    transform: Transform2D,
-    modules: Vec<Box<dyn Module2D>>,
-    resource_manager: Rc<RefCell<ResourceManager>>,
-    
-    parent: usize,
-    childerns: Vec<usize>,
+    parent: Option<ContainerId>,
+    children: Vec<ContainerId>,
 }
 
 impl Container2D {
-    pub fn empty(resource_manager: Rc<RefCell<ResourceManager>>) -> Self {
-        Self { transform : Transform2D::default(), modules : Vec::new(), resource_manager, childerns: Vec::new(), parent: 0 }
+    pub fn parent(&self) -> Option<ContainerId> {
+        self.parent
+    }
+    pub fn children(&self) -> &[ContainerId] {
+        &self.children
+    }
+}
+
+/// Composed from the `Transform2D` of the entity and its ancestors, an entity without one uses its parent's
+#[derive(Clone, Copy)]
+pub struct WorldTransform {
+    pub matrix: [f32; 6],
+    /// Depth-first position in the scene, sprites are drawn in this order
+    pub order: u32,
+}
+
+/// Bridge for `Module2D` implementors, they are updated and rendered in scene order
+pub struct Modules(pub Vec<Box<dyn Module2D>>);
+
+/// Entity hierarchy over a `World`. Transforms, sprites and animators are components,
+/// the built-in systems update and draw them
+pub struct Scene2D {
+    world: World,
+    /// Containers without a parent, in traversal order
+    roots: Vec<ContainerId>,
+    /// Runs `Module2D::on_paralel_update`
+    jobs: JobPool,
+    finished_clips: Vec<(ContainerId, String)>,
+}
+
+impl Scene2D {
+    // Starts worker threads, not something Default should do
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+        Self { world: World::new(), roots: Vec::new(), jobs: JobPool::new(workers), finished_clips: Vec::new() }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Spawns a root entity with `transform`
+    pub fn add(&mut self, transform: Transform2D) -> ContainerId {
+        let id = self.world.spawn();
+        self.world.insert(id, Container2D { parent: None, children: Vec::new() });
+        self.world.insert(id, transform);
+        self.world.insert(id, WorldTransform { matrix: IDENTITY, order: 0 });
+        self.roots.push(id);
+        id
+    }
+    pub fn add_child(&mut self, parent: ContainerId, transform: Transform2D) -> Result<ContainerId, String> {
+        if self.world.get::<Container2D>(parent).is_none() {
+            return Err(format!("Parent {:?} was removed", parent));
+        }
+        let id = self.add(transform);
+        self.set_parent(id, Some(parent))?;
+        Ok(id)
+    }
+
+    pub fn add_module(&mut self, id: ContainerId, module: Box<dyn Module2D>) {
+        if let Some(mut modules) = self.world.get_mut::<Modules>(id) {
+            modules.0.push(module);
+            return;
+        }
+        self.world.insert(id, Modules(vec![module]));
+    }
+
+    fn parent_of(&self, id: ContainerId) -> Result<Option<ContainerId>, String> {
+        self.world.get::<Container2D>(id).map(|x| x.parent).ok_or_else(|| format!("Container {:?} was removed", id))
+    }
+
+    /// Moves `id` with its children under `parent`, None makes it a root.
+    /// The local transform is kept, so the world transform follows the new parent
+    pub fn set_parent(&mut self, id: ContainerId, parent: Option<ContainerId>) -> Result<(), String> {
+        let old_parent = self.parent_of(id)?;
+        if let Some(parent) = parent {
+            // Walking up from the new parent must not reach the container itself
+            let mut ancestor = Some(parent);
+            while let Some(x) = ancestor {
+                if x == id {
+                    return Err(format!("Can't move {:?} under its own descendant {:?}", id, parent));
+                }
+                ancestor = self.parent_of(x)?;
+            }
+        }
+        self.detach(id, old_parent);
+        match parent {
+            Some(parent) => self.world.get_mut::<Container2D>(parent).unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        self.world.get_mut::<Container2D>(id).unwrap().parent = parent;
+        Ok(())
+    }
+
+    fn detach(&mut self, id: ContainerId, parent: Option<ContainerId>) {
+        match parent.and_then(|x| self.world.get_mut::<Container2D>(x)) {
+            Some(mut parent) => parent.children.retain(|x| *x != id),
+            None => self.roots.retain(|x| *x != id),
+        }
+    }
+
+    /// Despawns the container and its descendants, children are deleted before their parents.
+    /// Returns false if it was already removed
+    pub fn remove(&mut self, id: ContainerId) -> bool {
+        let Ok(parent) = self.parent_of(id) else {
+            return false;
+        };
+        self.detach(id, parent);
+        self.delete_recursive(id);
+        true
+    }
+
+    fn delete_recursive(&mut self, id: ContainerId) {
+        let Some(container) = self.world.remove::<Container2D>(id) else {
+            return;
+        };
+        for child in container.children {
+            self.delete_recursive(child);
+        }
+        if let Some(mut modules) = self.world.remove::<Modules>(id) {
+            for module in &mut modules.0 {
+                module.on_delete();
+            }
+        }
+        self.world.despawn(id);
+    }
+
+    pub fn roots(&self) -> &[ContainerId] {
+        &self.roots
+    }
+    /// Removes the root containers `keep` returns false for, along with their descendants
+    pub fn retain_roots(&mut self, keep: impl Fn(ContainerId) -> bool) {
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.roots).into_iter().partition(|x| keep(*x));
+        self.roots = kept;
+        for id in removed {
+            self.delete_recursive(id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for id in std::mem::take(&mut self.roots) {
+            self.delete_recursive(id);
+        }
+    }
+
+    /// Ids in depth-first order, parents before their children
+    fn traversal_order(&self) -> Vec<ContainerId> {
+        let mut order = Vec::new();
+        let Some(containers) = self.world.storage::<Container2D>() else {
+            return order;
+        };
+        let mut stack: Vec<ContainerId> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            let Some(container) = containers.get(id) else {
+                continue;
+            };
+            order.push(id);
+            stack.extend(container.children.iter().rev());
+        }
+        order
+    }
+
+    pub fn update_world_transforms(&mut self) {
+        let order = self.traversal_order();
+        let (Some(containers), Some(mut transforms), Some(mut world_transforms)) = (
+            self.world.storage::<Container2D>(),
+            self.world.storage_mut::<Transform2D>(),
+            self.world.storage_mut::<WorldTransform>(),
+        ) else {
+            return;
+        };
+        for (i, id) in order.into_iter().enumerate() {
+            let parent_matrix = containers.get(id)
+                .and_then(|x| x.parent)
+                .and_then(|x| world_transforms.get(x))
+                .map_or(IDENTITY, |x| x.matrix);
+            let matrix = match transforms.get_mut(id) {
+                Some(local) => transform::combine(&parent_matrix, local.transformation_matrix()),
+                None => parent_matrix,
+            };
+            world_transforms.insert(id, WorldTransform { matrix, order: i as u32 });
+        }
+    }
+
+    /// Runs `on_paralel_update` on the job workers for every module that doesn't opt out, the modules are moved out of the world and back
+    fn parallel_update(&mut self) {
+        let mut owners = Vec::new();
+        let mut modules = Vec::new();
+        self.world.query::<Modules>(|id, x| {
+            for (index, module) in x.0.iter_mut().enumerate() {
+                if module.wants_parallel_update() {
+                    owners.push((id, index));
+                    modules.push(std::mem::replace(module, Box::new(Moved)));
+                }
+            }
+        });
+        if modules.is_empty() {
+            return;
+        }
+        let modules = self.jobs.for_each(modules, |module| module.on_paralel_update());
+        // Modules come back in order, each goes back to the slot it left
+        let Some(mut storage) = self.world.storage_mut::<Modules>() else {
+            return;
+        };
+        for ((id, index), module) in owners.into_iter().zip(modules) {
+            storage.get_mut(id).unwrap().0[index] = module;
+        }
+    }
+
+    /// `delta` is in seconds. Every module's `on_update` runs before any `on_paralel_update`
+    pub fn update(&mut self, delta: f32) {
+        for id in self.traversal_order() {
+            if let Some(mut modules) = self.world.get_mut::<Modules>(id) {
+                for module in &mut modules.0 {
+                    module.on_update(delta);
+                }
+            }
+        }
+        self.parallel_update();
+        self.finished_clips.clear();
+        let mut advanced: Vec<SharedAnimator> = Vec::new();
+        self.world.query::<SharedAnimator>(|id, animator| {
+            // An animator attached to several entities still plays at its own speed
+            if advanced.iter().any(|x| Rc::ptr_eq(x, animator)) {
+                return;
+            }
+            advanced.push(Rc::clone(animator));
+            let mut animator = animator.borrow_mut();
+            animator.advance(delta);
+            for clip in animator.take_finished() {
+                self.finished_clips.push((id, clip));
+            }
+        });
+        self.update_world_transforms();
+    }
+
+    /// Clips the animator components finished during the last `update`, with their entity
+    pub fn finished_clips(&self) -> &[(ContainerId, String)] {
+        &self.finished_clips
     }
-    pub fn on_update(&mut self){
 
-        for x in &mut self.modules {
-            x.on_update();
+    /// Sprites and animators are drawn in scene order, then the modules.
+    /// Modules get their container's world transform, changes to it aren't kept
+    pub fn render(&mut self, resource_manager: &mut ResourceManager) {
+        self.update_world_transforms();
+
+        let mut sprites = Vec::new();
+        self.world.query2::<Sprite, WorldTransform>(|_, sprite, world_transform| {
+            if let Some((state, mut instance)) = sprite.resolve(resource_manager) {
+                instance.transform = world_transform.matrix;
+                sprites.push((world_transform.order, state, instance));
+            }
+        });
+        self.world.query2::<SharedAnimator, WorldTransform>(|_, animator, world_transform| {
+            if let Some((state, instance)) = animator.borrow().sprite(resource_manager, SpriteInstance::new(&world_transform.matrix)) {
+                sprites.push((world_transform.order, state, instance));
+            }
+        });
+        sprites.sort_by_key(|(order, _, _)| *order);
+        for (_, state, instance) in sprites {
+            resource_manager.sprite_batch_mut().draw(state, instance);
+        }
+
+        for id in self.traversal_order() {
+            let Some(mut modules) = self.world.get_mut::<Modules>(id) else {
+                continue;
+            };
+            let matrix = self.world.get::<WorldTransform>(id).map_or(IDENTITY, |x| x.matrix);
+            let mut world = Transform2D::from_matrix(matrix);
+            for module in &mut modules.0 {
+                module.on_render(&mut world, resource_manager);
+            }
         }
     }
 }
-pub trait Module2D{
-    fn on_update(&mut self){}
-    /// Only to preform thread safe operations
+
+impl Drop for Scene2D {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Modules are moved to worker threads for `on_paralel_update`, so they must be Send
+pub trait Module2D: Send {
+    /// `delta` is in seconds
+    fn on_update(&mut self, _delta: f32){}
+    /// Runs on a job worker after every module's `on_update` and before rendering, modules of the scene
+    /// run at the same time in no particular order. Only the module's own state is reachable, the scene
+    /// and the `ResourceManager` stay on the main thread, and anything shared with other modules needs a lock
     fn on_paralel_update(&mut self){}
-    fn on_render(&mut self){}
+    /// Modules without a parallel phase can return false so they aren't moved to a worker every frame
+    fn wants_parallel_update(&self) -> bool {
+        true
+    }
+    fn on_render(&mut self, _transform: &mut Transform2D, _resource_manager: &mut ResourceManager){}
+    /// Called when the container is removed from its scene
     fn on_delete(&mut self){}
 }
 
+/// Keeps a module's slot while it is away on a job worker
+struct Moved;
+
+impl Module2D for Moved {
+    fn wants_parallel_update(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::animation::{AnimationClip, Animator, PlayMode};
+
+    fn shared_animator(frames: usize, mode: PlayMode) -> SharedAnimator {
+        let mut animator = Animator::new();
+        let sprites: Vec<String> = (0..frames).map(|i| format!("frame{}", i)).collect();
+        animator.add_clip("clip", AnimationClip::from_sprites(&sprites, 1.0, mode));
+        animator.play("clip", false).unwrap();
+        Rc::new(RefCell::new(animator))
+    }
+
+    #[test]
+    fn animator_components_advance_once_and_report_finished_clips() {
+        let mut scene = Scene2D::new();
+        let a = scene.add(Transform2D::default());
+        let b = scene.add(Transform2D::default());
+        let animator = shared_animator(2, PlayMode::Once);
+        scene.world_mut().insert(a, Rc::clone(&animator));
+        scene.world_mut().insert(b, Rc::clone(&animator));
+
+        scene.update(1.0);
+        assert_eq!(animator.borrow().frame_index(), 1);
+        assert!(scene.finished_clips().is_empty());
+        scene.update(1.0);
+        assert_eq!(scene.finished_clips().len(), 1);
+        assert_eq!(scene.finished_clips()[0].1, "clip");
+        // Only the last update's clips are reported
+        scene.update(1.0);
+        assert!(scene.finished_clips().is_empty());
+    }
+
+    struct RecordDelete {
+        name: &'static str,
+        deleted: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Module2D for RecordDelete {
+        fn on_delete(&mut self) {
+            self.deleted.lock().unwrap().push(self.name);
+        }
+    }
+
+    fn add_recorded(scene: &mut Scene2D, parent: Option<ContainerId>, name: &'static str, deleted: &Arc<Mutex<Vec<&'static str>>>) -> ContainerId {
+        let id = match parent {
+            Some(parent) => scene.add_child(parent, Transform2D::default()).unwrap(),
+            None => scene.add(Transform2D::default()),
+        };
+        scene.add_module(id, Box::new(RecordDelete { name, deleted: Arc::clone(deleted) }));
+        id
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut scene = Scene2D::new();
+        let root = scene.add(Transform2D::default());
+        let child = scene.add_child(root, Transform2D::default()).unwrap();
+        let grandchild = scene.add_child(child, Transform2D::default()).unwrap();
+
+        assert!(scene.set_parent(root, Some(grandchild)).is_err());
+        assert!(scene.set_parent(child, Some(child)).is_err());
+        // A rejected move leaves the hierarchy alone
+        assert_eq!(scene.roots(), [root]);
+        assert_eq!(scene.world().get::<Container2D>(child).unwrap().parent(), Some(root));
+
+        scene.set_parent(grandchild, None).unwrap();
+        assert_eq!(scene.roots(), [root, grandchild]);
+        assert!(scene.world().get::<Container2D>(child).unwrap().children().is_empty());
+        scene.set_parent(root, Some(grandchild)).unwrap();
+        assert_eq!(scene.roots(), [grandchild]);
+    }
+
+    #[test]
+    fn remove_deletes_children_before_parents() {
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let mut scene = Scene2D::new();
+        let root = add_recorded(&mut scene, None, "root", &deleted);
+        let a = add_recorded(&mut scene, Some(root), "a", &deleted);
+        add_recorded(&mut scene, Some(a), "a1", &deleted);
+        add_recorded(&mut scene, Some(root), "b", &deleted);
+        let other = add_recorded(&mut scene, None, "other", &deleted);
+
+        assert!(scene.remove(a));
+        assert_eq!(*deleted.lock().unwrap(), ["a1", "a"]);
+        assert!(!scene.remove(a));
+        assert!(!scene.world().is_alive(a));
+        assert_eq!(scene.world().get::<Container2D>(root).unwrap().children().len(), 1);
+
+        deleted.lock().unwrap().clear();
+        assert!(scene.remove(root));
+        assert_eq!(*deleted.lock().unwrap(), ["b", "root"]);
+        assert_eq!(scene.roots(), [other]);
+    }
+
+    #[test]
+    fn retain_roots_removes_the_rest_with_their_children() {
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let mut scene = Scene2D::new();
+        let old = add_recorded(&mut scene, None, "old", &deleted);
+        add_recorded(&mut scene, Some(old), "old_child", &deleted);
+        let new = add_recorded(&mut scene, None, "new", &deleted);
+
+        scene.retain_roots(|id| id == new);
+        assert_eq!(*deleted.lock().unwrap(), ["old_child", "old"]);
+        assert_eq!(scene.roots(), [new]);
+        assert!(!scene.world().is_alive(old));
+    }
+
+    struct CountUpdates {
+        parallel: bool,
+        updates: Arc<Mutex<Vec<(usize, bool)>>>,
+        id: usize,
+    }
+
+    impl Module2D for CountUpdates {
+        fn on_update(&mut self, _delta: f32) {
+            self.updates.lock().unwrap().push((self.id, false));
+        }
+        fn on_paralel_update(&mut self) {
+            self.updates.lock().unwrap().push((self.id, true));
+        }
+        fn wants_parallel_update(&self) -> bool {
+            self.parallel
+        }
+    }
+
+    #[test]
+    fn parallel_update_skips_modules_that_opt_out() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let mut scene = Scene2D::new();
+        let a = scene.add(Transform2D::default());
+        let b = scene.add(Transform2D::default());
+        for (id, owner, parallel) in [(0, a, true), (1, a, false), (2, a, true), (3, b, false)] {
+            scene.add_module(owner, Box::new(CountUpdates { parallel, updates: Arc::clone(&updates), id }));
+        }
+
+        scene.update(0.1);
+        let mut parallel: Vec<_> = updates.lock().unwrap().iter().filter(|x| x.1).map(|x| x.0).collect();
+        parallel.sort();
+        assert_eq!(parallel, [0, 2]);
+
+        // The modules came back to their own slots, in order
+        updates.lock().unwrap().clear();
+        scene.update(0.1);
+        let sequential: Vec<_> = updates.lock().unwrap().iter().filter(|x| !x.1).map(|x| x.0).collect();
+        assert_eq!(sequential, [0, 1, 2, 3]);
+    }
+
+    struct ParallelOnly(Arc<Mutex<u32>>);
+
+    impl Module2D for ParallelOnly {
+        fn on_paralel_update(&mut self) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn parallel_update_runs_by_default() {
+        let count = Arc::new(Mutex::new(0));
+        let mut scene = Scene2D::new();
+        for _ in 0..3 {
+            let id = scene.add(Transform2D::default());
+            scene.add_module(id, Box::new(ParallelOnly(Arc::clone(&count))));
+        }
+        scene.update(0.1);
+        assert_eq!(*count.lock().unwrap(), 3);
+        scene.update(0.1);
+        assert_eq!(*count.lock().unwrap(), 6);
+    }
+}
@@ -0,0 +1,265 @@
+use std::any::TypeId;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+
+use downcast_rs::{impl_downcast, Downcast};
+
+use crate::handle::{Handle, Storage};
+
+pub struct EntityTag;
+/// Stays valid until the entity is despawned, a reused slot gets a new generation
+pub type Entity = Handle<EntityTag>;
+
+/// Components of one type, packed so iterating them doesn't skip over other entities
+pub struct SparseSet<T> {
+    /// Entity index -> position in `entities` and `components`
+    sparse: Vec<Option<u32>>,
+    entities: Vec<Entity>,
+    components: Vec<T>,
+}
+
+impl<T> SparseSet<T> {
+    fn new() -> Self {
+        Self { sparse: Vec::new(), entities: Vec::new(), components: Vec::new() }
+    }
+
+    fn position(&self, entity: Entity) -> Option<usize> {
+        let position = (*self.sparse.get(entity.index())?)? as usize;
+        // The slot may belong to an older generation of the entity
+        (self.entities[position] == entity).then_some(position)
+    }
+
+    /// Returns the replaced component
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(position) = self.position(entity) {
+            return Some(std::mem::replace(&mut self.components[position], component));
+        }
+        if self.sparse.len() <= entity.index() {
+            self.sparse.resize(entity.index() + 1, None);
+        }
+        self.sparse[entity.index()] = Some(self.entities.len() as u32);
+        self.entities.push(entity);
+        self.components.push(component);
+        None
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let position = self.position(entity)?;
+        self.sparse[entity.index()] = None;
+        self.entities.swap_remove(position);
+        if let Some(moved) = self.entities.get(position) {
+            self.sparse[moved.index()] = Some(position as u32);
+        }
+        Some(self.components.swap_remove(position))
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.position(entity).map(|x| &self.components[x])
+    }
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.position(entity).map(|x| &mut self.components[x])
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(self.components.iter_mut())
+    }
+}
+
+/// Type erased `SparseSet`, so despawning can reach every storage
+trait ComponentStorage: Downcast {
+    fn remove_entity(&mut self, entity: Entity);
+}
+impl_downcast!(ComponentStorage);
+
+impl<T: 'static> ComponentStorage for SparseSet<T> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+}
+
+/// Entities and their components, any 'static type can be a component.
+/// Each component type is borrowed separately, so systems can query several types through `&World`
+/// as long as they don't borrow the same type mutably twice
+pub struct World {
+    entities: Storage<EntityTag>,
+    storages: HashMap<TypeId, RefCell<Box<dyn ComponentStorage>>>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self { entities: Storage::new(), storages: HashMap::new() }
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        self.entities.push(EntityTag)
+    }
+    /// Drops the entity's components, returns false if it was already despawned
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if self.entities.remove_handle(entity).is_none() {
+            return false;
+        }
+        for storage in self.storages.values_mut() {
+            storage.get_mut().remove_entity(entity);
+        }
+        true
+    }
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.get(entity).is_some()
+    }
+
+    /// Returns the replaced component. Components of despawned entities are dropped
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.is_alive(entity) {
+            println!("Can't add a {} to despawned entity {:?}", std::any::type_name::<T>(), entity);
+            return None;
+        }
+        self.storages.entry(TypeId::of::<T>())
+            .or_insert_with(|| RefCell::new(Box::new(SparseSet::<T>::new())))
+            .get_mut()
+            .downcast_mut::<SparseSet<T>>()
+            .unwrap()
+            .insert(entity, component)
+    }
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storages.get_mut(&TypeId::of::<T>())?.get_mut().downcast_mut::<SparseSet<T>>().unwrap().remove(entity)
+    }
+
+    /// None if no entity ever had a `T`
+    pub fn storage<T: 'static>(&self) -> Option<Ref<'_, SparseSet<T>>> {
+        let storage = self.storages.get(&TypeId::of::<T>())?.borrow();
+        Some(Ref::map(storage, |x| x.downcast_ref::<SparseSet<T>>().unwrap()))
+    }
+    pub fn storage_mut<T: 'static>(&self) -> Option<RefMut<'_, SparseSet<T>>> {
+        let storage = self.storages.get(&TypeId::of::<T>())?.borrow_mut();
+        Some(RefMut::map(storage, |x| x.downcast_mut::<SparseSet<T>>().unwrap()))
+    }
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.storage::<T>()?, |x| x.get(entity)).ok()
+    }
+    pub fn get_mut<T: 'static>(&self, entity: Entity) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.storage_mut::<T>()?, |x| x.get_mut(entity)).ok()
+    }
+
+    /// Calls `f` for every entity with an `A`
+    pub fn query<A: 'static>(&self, mut f: impl FnMut(Entity, &mut A)) {
+        let Some(mut a) = self.storage_mut::<A>() else {
+            return;
+        };
+        for (entity, x) in a.iter_mut() {
+            f(entity, x);
+        }
+    }
+    /// Calls `f` for every entity with both an `A` and a `B`, `A` should be the rarer one
+    pub fn query2<A: 'static, B: 'static>(&self, mut f: impl FnMut(Entity, &mut A, &mut B)) {
+        let (Some(mut a), Some(mut b)) = (self.storage_mut::<A>(), self.storage_mut::<B>()) else {
+            return;
+        };
+        for (entity, x) in a.iter_mut() {
+            if let Some(y) = b.get_mut(entity) {
+                f(entity, x, y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_set_swap_remove_keeps_positions() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..4).map(|_| world.spawn()).collect();
+        let mut set = SparseSet::new();
+        for (i, entity) in entities.iter().enumerate() {
+            assert!(set.insert(*entity, i).is_none());
+        }
+        // Removing the first moves the last into its place
+        assert_eq!(set.remove(entities[0]), Some(0));
+        assert_eq!(set.remove(entities[0]), None);
+        assert_eq!(set.get(entities[3]), Some(&3));
+        assert_eq!(set.get(entities[1]), Some(&1));
+        assert_eq!(set.remove(entities[3]), Some(3));
+        assert_eq!(set.get(entities[2]), Some(&2));
+
+        assert_eq!(set.insert(entities[0], 10), None);
+        assert_eq!(set.insert(entities[0], 11), Some(10));
+        let mut items: Vec<(Entity, usize)> = set.iter_mut().map(|(entity, x)| (entity, *x)).collect();
+        items.sort();
+        let mut expected = vec![(entities[0], 11), (entities[1], 1), (entities[2], 2)];
+        expected.sort();
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn components_follow_their_entity() {
+        let mut world = World::new();
+        let a = world.spawn();
+        let b = world.spawn();
+        world.insert(a, 1u32);
+        world.insert(b, 2u32);
+        world.insert(b, "b");
+        assert_eq!(world.remove::<u32>(a), Some(1));
+        assert!(world.get::<u32>(a).is_none());
+        assert_eq!(*world.get::<u32>(b).unwrap(), 2);
+        *world.get_mut::<u32>(b).unwrap() += 1;
+        assert_eq!(*world.get::<u32>(b).unwrap(), 3);
+        assert!(world.get::<f32>(b).is_none());
+
+        world.insert(a, 5u32);
+        assert_eq!(*world.get::<u32>(a).unwrap(), 5);
+    }
+
+    #[test]
+    fn despawned_entities_go_stale() {
+        let mut world = World::new();
+        let a = world.spawn();
+        world.insert(a, 1u32);
+        assert!(world.despawn(a));
+        assert!(!world.despawn(a));
+        assert!(!world.is_alive(a));
+
+        // The slot is reused with a new generation
+        let b = world.spawn();
+        assert_eq!(a.index(), b.index());
+        assert_ne!(a, b);
+        assert!(world.get::<u32>(b).is_none());
+        world.insert(b, 2u32);
+        assert!(world.get::<u32>(a).is_none());
+        assert_eq!(world.remove::<u32>(a), None);
+        assert!(world.insert(a, 3u32).is_none());
+        assert_eq!(*world.get::<u32>(b).unwrap(), 2);
+    }
+
+    #[test]
+    fn query2_joins_both_components() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..4).map(|_| world.spawn()).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            world.insert(*entity, i as u32);
+        }
+        world.insert(entities[1], "one");
+        world.insert(entities[3], "three");
+        world.despawn(entities[3]);
+
+        let mut joined = Vec::new();
+        world.query2::<&str, u32>(|entity, name, value| {
+            *value += 10;
+            joined.push((entity, *name));
+        });
+        assert_eq!(joined, [(entities[1], "one")]);
+        assert_eq!(*world.get::<u32>(entities[1]).unwrap(), 11);
+        assert_eq!(*world.get::<u32>(entities[0]).unwrap(), 0);
+
+        let mut count = 0;
+        world.query::<u32>(|_, _| count += 1);
+        assert_eq!(count, 3);
+        world.query2::<f32, u32>(|_, _, _| panic!("no entity has an f32"));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// Typed index into a `Storage<T>`, the generation tells a removed resource apart from the one reusing its slot
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    /// Slot index, unique among the live handles of a storage
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+}
+
+// Derives would require T: Clone etc.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Handle<T> {}
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.index, self.generation) == (other.index, other.generation)
+    }
+}
+impl<T> Eq for Handle<T> {}
+impl<T> PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T> Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.index, self.generation).cmp(&(other.index, other.generation))
+    }
+}
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.index, self.generation).hash(state);
+    }
+}
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({}v{})", std::any::type_name::<T>(), self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Resources of one type, addressed by handle or by key
+pub struct Storage<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    keys: HashMap<String, Handle<T>>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Storage<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Replacing a key keeps its handle, so handles taken before a reload stay valid
+    pub fn insert(&mut self, key: &str, value: T) -> (Handle<T>, Option<T>) {
+        if let Some(&handle) = self.keys.get(key) {
+            let old = self.slots[handle.index as usize].value.replace(value);
+            return (handle, old);
+        }
+        let handle = self.push(value);
+        self.keys.insert(key.to_string(), handle);
+        (handle, None)
+    }
+
+    /// Adds a value without a key, it can only be reached through the returned handle
+    pub fn push(&mut self, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, value: Some(value) });
+                self.slots.len() as u32 - 1
+            }
+        };
+        Handle { index, generation: self.slots[index as usize].generation, marker: PhantomData }
+    }
+
+    pub fn handle(&self, key: &str) -> Option<Handle<T>> {
+        self.keys.get(key).copied()
+    }
+    /// None if the handle's resource was removed
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let handle = self.keys.remove(key)?;
+        self.remove_handle(handle)
+    }
+    /// For values added with `push`, a keyed value would keep its key
+    pub fn remove_handle(&mut self, handle: Handle<T>) -> Option<T> {
+        self.get(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        slot.value.take()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().filter_map(|x| x.value.as_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_replace_keep_the_handle() {
+        let mut storage = Storage::new();
+        let (a, old) = storage.insert("a", 1);
+        assert!(old.is_none());
+        let (b, _) = storage.insert("b", 2);
+        assert_ne!(a, b);
+        assert_eq!(storage.handle("a"), Some(a));
+        assert_eq!(storage.handle("missing"), None);
+
+        let (replaced, old) = storage.insert("a", 10);
+        assert_eq!(replaced, a);
+        assert_eq!(old, Some(1));
+        assert_eq!(storage.get(a), Some(&10));
+        *storage.get_mut(b).unwrap() += 1;
+        assert_eq!(storage.get(b), Some(&3));
+    }
+
+    #[test]
+    fn removed_slots_are_reused_with_a_new_generation() {
+        let mut storage = Storage::new();
+        let (a, _) = storage.insert("a", "first");
+        let (b, _) = storage.insert("b", "second");
+
+        assert_eq!(storage.remove("a"), Some("first"));
+        assert_eq!(storage.handle("a"), None);
+        assert_eq!(storage.get(a), None);
+        assert!(storage.get_mut(a).is_none());
+        assert_eq!(storage.remove("a"), None);
+
+        let c = storage.push("third");
+        assert_eq!(c.index(), a.index());
+        assert_ne!(c, a);
+        // The stale handle still can't see the new value
+        assert_eq!(storage.get(a), None);
+        assert_eq!(storage.get(c), Some(&"third"));
+        assert_eq!(storage.get(b), Some(&"second"));
+    }
+
+    #[test]
+    fn remove_handle_only_takes_live_values() {
+        let mut storage = Storage::new();
+        let a = storage.push(1);
+        assert_eq!(storage.remove_handle(a), Some(1));
+        assert_eq!(storage.remove_handle(a), None);
+
+        // A double remove must not free the slot twice
+        let b = storage.push(2);
+        let c = storage.push(3);
+        assert_ne!(b.index(), c.index());
+        let mut values: Vec<_> = storage.values_mut().map(|x| *x).collect();
+        values.sort();
+        assert_eq!(values, [2, 3]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::ffi::{c_char, c_void, CString};
+
+use libloading::Library;
+
+// EGL 1.5 and EGL_MESA_platform_surfaceless, EGL is loaded at runtime so nothing links against it
+type EGLDisplay = *mut c_void;
+type EGLConfig = *mut c_void;
+type EGLContext = *mut c_void;
+type EGLint = i32;
+type EGLenum = u32;
+type EGLBoolean = u32;
+
+const EGL_NONE: EGLint = 0x3038;
+const EGL_SURFACE_TYPE: EGLint = 0x3033;
+const EGL_RENDERABLE_TYPE: EGLint = 0x3040;
+const EGL_OPENGL_BIT: EGLint = 0x0008;
+const EGL_OPENGL_API: EGLenum = 0x30A2;
+const EGL_CONTEXT_MAJOR_VERSION: EGLint = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: EGLint = 0x30FB;
+const EGL_CONTEXT_OPENGL_PROFILE_MASK: EGLint = 0x30FD;
+const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: EGLint = 0x0001;
+const EGL_PLATFORM_SURFACELESS_MESA: EGLenum = 0x31DD;
+
+type GetProcAddress = unsafe extern "C" fn(*const c_char) -> *const c_void;
+type GetPlatformDisplay = unsafe extern "C" fn(EGLenum, *mut c_void, *const isize) -> EGLDisplay;
+type GetPlatformDisplayExt = unsafe extern "C" fn(EGLenum, *mut c_void, *const EGLint) -> EGLDisplay;
+type Initialize = unsafe extern "C" fn(EGLDisplay, *mut EGLint, *mut EGLint) -> EGLBoolean;
+type ChooseConfig = unsafe extern "C" fn(EGLDisplay, *const EGLint, *mut EGLConfig, EGLint, *mut EGLint) -> EGLBoolean;
+type BindApi = unsafe extern "C" fn(EGLenum) -> EGLBoolean;
+type CreateContext = unsafe extern "C" fn(EGLDisplay, EGLConfig, EGLContext, *const EGLint) -> EGLContext;
+type MakeCurrent = unsafe extern "C" fn(EGLDisplay, *mut c_void, *mut c_void, EGLContext) -> EGLBoolean;
+type DestroyContext = unsafe extern "C" fn(EGLDisplay, EGLContext) -> EGLBoolean;
+type Terminate = unsafe extern "C" fn(EGLDisplay) -> EGLBoolean;
+type GetError = unsafe extern "C" fn() -> EGLint;
+
+/// OpenGL 3.3 core context without a window, display server or default framebuffer, made current
+/// on the creating thread. Uses EGL's surfaceless platform (Mesa, llvmpipe works without a GPU),
+/// so drawing has to go to a framebuffer object
+pub struct HeadlessContext {
+    egl: Library,
+    display: EGLDisplay,
+    context: EGLContext,
+}
+
+impl HeadlessContext {
+    pub fn create() -> Result<HeadlessContext, String> {
+        let egl = unsafe { Library::new("libEGL.so.1") }
+            .map_err(|e| format!("Failed to load libEGL.so.1: {}", e))?;
+        unsafe {
+            let get_error = *egl.get::<GetError>(b"eglGetError\0").map_err(|e| e.to_string())?;
+            let fail = |call: &str| format!("{} failed, EGL error 0x{:X}", call, get_error());
+
+            let display = match egl.get::<GetPlatformDisplay>(b"eglGetPlatformDisplay\0") {
+                Ok(f) => f(EGL_PLATFORM_SURFACELESS_MESA, std::ptr::null_mut(), [EGL_NONE as isize].as_ptr()),
+                // EGL 1.4 only has the extension
+                Err(_) => {
+                    let get_proc_address = *egl.get::<GetProcAddress>(b"eglGetProcAddress\0").map_err(|e| e.to_string())?;
+                    let f = get_proc_address(c"eglGetPlatformDisplayEXT".as_ptr());
+                    if f.is_null() {
+                        return Err("EGL has no eglGetPlatformDisplay".to_string());
+                    }
+                    let f: GetPlatformDisplayExt = std::mem::transmute(f);
+                    f(EGL_PLATFORM_SURFACELESS_MESA, std::ptr::null_mut(), [EGL_NONE].as_ptr())
+                }
+            };
+            if display.is_null() {
+                return Err(fail("eglGetPlatformDisplay(EGL_PLATFORM_SURFACELESS_MESA)"));
+            }
+            let initialize = *egl.get::<Initialize>(b"eglInitialize\0").map_err(|e| e.to_string())?;
+            if initialize(display, std::ptr::null_mut(), std::ptr::null_mut()) == 0 {
+                return Err(fail("eglInitialize"));
+            }
+            // Any surface type, the context is never bound to a surface
+            let config_attributes = [EGL_SURFACE_TYPE, 0, EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT, EGL_NONE];
+            let mut config: EGLConfig = std::ptr::null_mut();
+            let mut count = 0;
+            let choose_config = *egl.get::<ChooseConfig>(b"eglChooseConfig\0").map_err(|e| e.to_string())?;
+            if choose_config(display, config_attributes.as_ptr(), &mut config, 1, &mut count) == 0 || count == 0 {
+                return Err(fail("eglChooseConfig"));
+            }
+            let bind_api = *egl.get::<BindApi>(b"eglBindAPI\0").map_err(|e| e.to_string())?;
+            if bind_api(EGL_OPENGL_API) == 0 {
+                return Err(fail("eglBindAPI(EGL_OPENGL_API)"));
+            }
+            let context_attributes = [
+                EGL_CONTEXT_MAJOR_VERSION, 3,
+                EGL_CONTEXT_MINOR_VERSION, 3,
+                EGL_CONTEXT_OPENGL_PROFILE_MASK, EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                EGL_NONE,
+            ];
+            let create_context = *egl.get::<CreateContext>(b"eglCreateContext\0").map_err(|e| e.to_string())?;
+            let context = create_context(display, config, std::ptr::null_mut(), context_attributes.as_ptr());
+            if context.is_null() {
+                return Err(fail("eglCreateContext"));
+            }
+            let x = HeadlessContext { egl, display, context };
+            let make_current = *x.egl.get::<MakeCurrent>(b"eglMakeCurrent\0").map_err(|e| e.to_string())?;
+            if make_current(display, std::ptr::null_mut(), std::ptr::null_mut(), context) == 0 {
+                return Err(fail("eglMakeCurrent"));
+            }
+            Ok(x)
+        }
+    }
+
+    /// Current context with the GL functions loaded for a test, None (the test skips) without libEGL.
+    /// Only a missing EGL skips, a broken one fails the test
+    #[cfg(test)]
+    pub(crate) fn for_test() -> Option<HeadlessContext> {
+        let context = match HeadlessContext::create() {
+            Ok(x) => x,
+            Err(e) if e.starts_with("Failed to load libEGL") => {
+                println!("Skipping, {}", e);
+                return None;
+            }
+            Err(e) => panic!("{}", e),
+        };
+        gl::load_with(|symbol| context.get_proc_address(symbol));
+        Some(context)
+    }
+
+    /// For `gl::load_with`
+    pub fn get_proc_address(&self, name: &str) -> *const c_void {
+        let Ok(name) = CString::new(name) else {
+            return std::ptr::null();
+        };
+        unsafe {
+            match self.egl.get::<GetProcAddress>(b"eglGetProcAddress\0") {
+                Ok(f) => f(name.as_ptr()),
+                Err(_) => std::ptr::null(),
+            }
+        }
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        unsafe {
+            if let Ok(make_current) = self.egl.get::<MakeCurrent>(b"eglMakeCurrent\0") {
+                make_current(self.display, std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut());
+            }
+            if let Ok(destroy_context) = self.egl.get::<DestroyContext>(b"eglDestroyContext\0") {
+                destroy_context(self.display, self.context);
+            }
+            if let Ok(terminate) = self.egl.get::<Terminate>(b"eglTerminate\0") {
+                terminate(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture;
+    use crate::render_target::RenderTarget;
+
+    #[test]
+    fn renders_without_a_display() {
+        let Some(_context) = HeadlessContext::for_test() else {
+            return;
+        };
+        let target = RenderTarget::create_new(4, 2, false).unwrap();
+        target.bind();
+        unsafe {
+            gl::ClearColor(1.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+        let frame = capture::read_framebuffer();
+        assert_eq!(frame.dimensions(), (4, 2));
+        assert!(frame.pixels().all(|x| x.0 == [255, 0, 0, 255]));
+    }
+}
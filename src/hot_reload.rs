@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::atlas::AtlasOptions;
+use crate::sampler::SamplerDesc;
+use crate::shader::{ShaderError, ShaderSource, ShaderStage};
+use crate::vfs;
+
+#[derive(Clone, PartialEq)]
+pub enum ShaderFiles {
+    Separate { vertex: String, fragment: String },
+    Single(String),
+}
+
+impl ShaderFiles {
+    pub fn load(&self, defines: &[(String, String)]) -> Result<Vec<(ShaderStage, ShaderSource)>, ShaderError> {
+        match self {
+            ShaderFiles::Separate { vertex, fragment } => Ok(vec![
+                (ShaderStage::Vertex, ShaderSource::from_file(vertex, defines)?),
+                (ShaderStage::Fragment, ShaderSource::from_file(fragment, defines)?),
+            ]),
+            ShaderFiles::Single(path) => ShaderSource::stages_from_file(path, defines),
+        }
+    }
+}
+
+/// Files a resource was built from, used to rebuild it when one of them changes
+#[derive(Clone, PartialEq)]
+pub enum ResourceSource {
+    Shader {
+        files: ShaderFiles,
+        defines: Vec<(String, String)>,
+        /// Every file read while preprocessing, includes too
+        dependencies: Vec<PathBuf>,
+    },
+    Texture { path: String, srgb: bool, sampler: SamplerDesc },
+    /// (region name, path) pairs
+    Atlas { files: Vec<(String, String)>, options: AtlasOptions },
+}
+
+impl ResourceSource {
+    pub fn shader(files: ShaderFiles, defines: Vec<(String, String)>, stages: &[(ShaderStage, ShaderSource)]) -> Self {
+        let dependencies = stages.iter()
+            .flat_map(|(_, source)| source.files.iter().map(|x| PathBuf::from(&x.path)))
+            .collect();
+        ResourceSource::Shader { files, defines, dependencies }
+    }
+    pub fn files(&self) -> Vec<PathBuf> {
+        match self {
+            ResourceSource::Shader { dependencies, .. } => dependencies.clone(),
+            ResourceSource::Texture { path, .. } => vec![PathBuf::from(path)],
+            ResourceSource::Atlas { files, .. } => files.iter().map(|(_, path)| PathBuf::from(path)).collect(),
+        }
+    }
+}
+
+/// Watches the directories of the registered files, editors often save by replacing the file
+/// which would drop a watch placed on the file itself
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    directories: HashSet<PathBuf>,
+    files: HashSet<PathBuf>,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl FileWatcher {
+    /// Saves usually arrive as several events, a file is reported once it has been quiet this long
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Result<FileWatcher, String> {
+        let (sender, events) = channel();
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+        Ok(FileWatcher {
+            watcher,
+            events,
+            directories: HashSet::new(),
+            files: HashSet::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn watch(&mut self, path: &Path) {
+        let Some(path) = Self::normalize(path) else {
+            println!("Can't watch \"{}\", file not found", path.display());
+            return;
+        };
+        if let Some(directory) = path.parent() {
+            if !self.directories.contains(directory) {
+                match self.watcher.watch(directory, RecursiveMode::NonRecursive) {
+                    Ok(()) => { self.directories.insert(directory.to_path_buf()); }
+                    Err(e) => println!("Failed to watch \"{}\": {}", directory.display(), e),
+                }
+            }
+        }
+        self.files.insert(path);
+    }
+
+    /// Returns the watched files that changed and have settled since the last call
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        while let Ok(event) = self.events.try_recv() {
+            let event = match event {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("File watcher error: {}", e);
+                    continue;
+                }
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if self.files.contains(&path) {
+                    self.pending.insert(path, now);
+                }
+            }
+        }
+        let ready: Vec<PathBuf> = self.pending.iter()
+            .filter(|(_, time)| now.duration_since(**time) >= Self::DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready
+    }
+
+    /// Event paths are absolute, so watched paths are resolved through the VFS and canonicalized to compare against them.
+    /// None for files that only exist in an archive
+    pub fn normalize(path: &Path) -> Option<PathBuf> {
+        vfs::real_path(&path.to_string_lossy()).and_then(|x| std::fs::canonicalize(x).ok())
+    }
+}
@@ -0,0 +1,129 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Worker threads for short CPU jobs. Jobs own their data, it is moved to a worker and back,
+/// so nothing borrowed from the main thread (like the `ResourceManager`) can reach them
+pub struct JobPool {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobPool {
+    pub fn new(worker_count: usize) -> Self {
+        let (jobs, job_receiver) = channel::<Job>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..worker_count.max(1))
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("job-worker-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before running so the other workers can take jobs
+                        let job = match jobs.lock().unwrap().recv() {
+                            Ok(x) => x,
+                            Err(_) => return,
+                        };
+                        job();
+                    })
+                    .expect("Failed to spawn job worker thread")
+            })
+            .collect();
+        Self { jobs: Some(jobs), workers }
+    }
+
+    /// Calls `f` on every item, split in one batch per worker, and blocks until all are done.
+    /// Items come back in their original order. A panicking call is reported and its item kept
+    pub fn for_each<T: Send + 'static>(&self, items: Vec<T>, f: impl Fn(&mut T) + Send + Sync + 'static) -> Vec<T> {
+        let run = move |item: &mut T| {
+            if catch_unwind(AssertUnwindSafe(|| f(item))).is_err() {
+                println!("Parallel job panicked");
+            }
+        };
+        // Not worth a round trip through the workers
+        if items.len() <= 1 || self.workers.len() <= 1 {
+            let mut items = items;
+            items.iter_mut().for_each(run);
+            return items;
+        }
+
+        let run = Arc::new(run);
+        let count = items.len();
+        let batch_size = count.div_ceil(self.workers.len());
+        let (result_sender, results) = channel();
+        let mut items = items.into_iter();
+        let mut batch_count = 0;
+        loop {
+            let mut batch: Vec<T> = items.by_ref().take(batch_size).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let run = run.clone();
+            let results = result_sender.clone();
+            let index = batch_count;
+            let job: Job = Box::new(move || {
+                batch.iter_mut().for_each(|x| run(x));
+                let _ = results.send((index, batch));
+            });
+            self.jobs.as_ref().expect("Job workers have stopped").send(job).expect("Job workers have stopped");
+            batch_count += 1;
+        }
+        drop(result_sender);
+
+        let mut batches: Vec<(usize, Vec<T>)> = results.iter().collect();
+        assert_eq!(batches.len(), batch_count, "Job workers have stopped");
+        batches.sort_by_key(|(index, _)| *index);
+        let mut items = Vec::with_capacity(count);
+        for (_, batch) in batches {
+            items.extend(batch);
+        }
+        items
+    }
+}
+
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers once they finish their current job
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_keep_their_order() {
+        let pool = JobPool::new(4);
+        let items: Vec<u32> = (0..103).collect();
+        let result = pool.for_each(items, |x| *x *= 2);
+        assert_eq!(result, (0..103).map(|x| x * 2).collect::<Vec<_>>());
+
+        // Fewer items than workers, and the inline paths
+        assert_eq!(pool.for_each(vec![1, 2], |x| *x += 1), [2, 3]);
+        assert_eq!(pool.for_each(vec![5], |x| *x += 1), [6]);
+        assert!(pool.for_each(Vec::<u32>::new(), |x| *x += 1).is_empty());
+        assert_eq!(JobPool::new(1).for_each(vec![1, 2, 3], |x| *x *= 3), [3, 6, 9]);
+    }
+
+    #[test]
+    fn panicking_items_are_kept_and_the_pool_survives() {
+        let pool = JobPool::new(2);
+        let result = pool.for_each((0..10).collect(), |x: &mut i32| {
+            if *x == 3 {
+                panic!("job failed");
+            }
+            *x = -*x;
+        });
+        assert_eq!(result, [0, -1, -2, 3, -4, -5, -6, -7, -8, -9]);
+
+        // The workers are still there for the next call
+        assert_eq!(pool.for_each(vec![1, 2, 3, 4], |x| *x += 1), [2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,27 @@
+pub mod buffers;
+pub mod shader;
+pub mod texture;
+pub mod mesh;
+pub mod lua_bindings;
+pub mod camera;
+pub mod transform;
+pub mod resource_manager;
+pub mod container;
+pub mod capture;
+pub mod render_target;
+pub mod sprite_batch;
+pub mod uniform;
+pub mod hot_reload;
+pub mod blend;
+pub mod sampler;
+pub mod atlas;
+pub mod animation;
+pub mod asset_loader;
+pub mod handle;
+pub mod manifest;
+pub mod ecs;
+pub mod vfs;
+pub mod jobs;
+pub mod headless;
+#[cfg(test)]
+mod test_files;
@@ -1,87 +1,705 @@
-use std::{cell::RefCell, fs::File, io::Read, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 use glam::Vec2;
 use mlua::prelude::*;
 
-use crate::{camera::Camera, mesh::{Mesh, PlanarTextureVertex}, resource_manager::ResourceManager, shader::Shader, texture::Texture};
+use crate::{asset_loader::AssetRequest, animation::{self, AnimationClip, Animator, PlayMode, SharedAnimator}, atlas::{self, AtlasOptions}, container::{Container2D, ContainerId, Scene2D, WorldTransform}, blend::BlendMode, camera::Camera, capture, mesh::{Mesh, PlanarTextureVertex, Topology}, render_target::RenderTarget, resource_manager::ResourceManager, sampler::{Filter, MipmapMode, Sampler, SamplerDesc, WrapMode}, hot_reload::ShaderFiles, shader::Shader, sprite_batch::{Sprite, SpriteInstance}, transform::Transform2D, uniform, vfs};
 pub fn reload_and_execute_script(lua: &Lua, script_path: &str) -> LuaResult<()> {
     // Read the Lua script
-    let script_content = std::fs::read_to_string(script_path)
+    let script_content = vfs::read_to_string(script_path)
         .map_err(|e| LuaError::RuntimeError(format!("Failed to read script: {}", e)))?;
 
     // Load and execute the script
-    lua.load(&script_content).exec()?;
+    lua.load(&script_content).set_name(script_path).exec()?;
 
     Ok(())
 }
-pub fn bind_lua(lua :&Lua, resource_manager : &Rc<RefCell<ResourceManager>>){
-    let resource_manager_clone = Rc::clone(&resource_manager);
-    let resource_manager_clone_2 = Rc::clone(&resource_manager);
-    let resource_manager_clone_3 = Rc::clone(&resource_manager);
-    let resource_manager_clone_4 = Rc::clone(&resource_manager);
-    let resource_manager_clone_5 = Rc::clone(&resource_manager);
+/// Lua handle to a shader resource, looked up by key on every call so reloads are picked up
+struct LuaShader {
+    key: String,
+    resource_manager: Rc<RefCell<ResourceManager>>,
+}
+impl LuaUserData for LuaShader {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("set", |_, this, x: (String, LuaValue)| {
+            let rm = this.resource_manager.borrow();
+            let shader = rm.get_resource::<Shader>(&this.key)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Shader \"{}\" not found", this.key)))?;
+            set_uniform_from_lua(shader, &x.0, x.1).map_err(LuaError::RuntimeError)
+        });
+        methods.add_method("uniforms", |lua, this, ()| {
+            let rm = this.resource_manager.borrow();
+            let shader = rm.get_resource::<Shader>(&this.key)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Shader \"{}\" not found", this.key)))?;
+            let result = lua.create_table()?;
+            for (name, info) in shader.uniforms() {
+                let entry = lua.create_table()?;
+                entry.set("type", uniform::type_name(info.gl_type))?;
+                entry.set("size", info.size)?;
+                result.set(name.as_str(), entry)?;
+            }
+            Ok(result)
+        });
+        methods.add_method("uniform_blocks", |lua, this, ()| {
+            let rm = this.resource_manager.borrow();
+            let shader = rm.get_resource::<Shader>(&this.key)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Shader \"{}\" not found", this.key)))?;
+            let result = lua.create_table()?;
+            for (name, info) in shader.uniform_blocks() {
+                let entry = lua.create_table()?;
+                entry.set("index", info.index)?;
+                entry.set("data_size", info.data_size)?;
+                result.set(name.as_str(), entry)?;
+            }
+            Ok(result)
+        });
+    }
+}
+
+fn set_uniform_from_lua(shader: &Shader, name: &str, value: LuaValue) -> Result<(), String> {
+    let info = shader.uniform(name)
+        .ok_or_else(|| format!("Uniform \"{}\" is not an active uniform", name))?;
+    let numbers = |count: usize| -> Result<Vec<f32>, String> {
+        let table = value.as_table().ok_or_else(|| format!("Uniform \"{}\" expects a table of {} numbers", name, count))?;
+        // Accept both {x=, y=, z=, w=} and sequences
+        let mut result: Vec<f32> = table.sequence_values::<f32>().filter_map(|x| x.ok()).collect();
+        if result.is_empty() {
+            result = ["x", "y", "z", "w"].iter().take(count).filter_map(|k| table.get::<f32>(*k).ok()).collect();
+        }
+        if result.len() != count {
+            return Err(format!("Uniform \"{}\" expects {} numbers, got {}", name, count, result.len()));
+        }
+        Ok(result)
+    };
+    match info.gl_type {
+        gl::FLOAT => shader.set_uniform(name, &(value.as_f32().ok_or_else(|| format!("Uniform \"{}\" expects a number", name))?)),
+        gl::FLOAT_VEC2 => shader.set_uniform(name, &<[f32; 2]>::try_from(numbers(2)?).unwrap()),
+        gl::FLOAT_VEC3 => shader.set_uniform(name, &<[f32; 3]>::try_from(numbers(3)?).unwrap()),
+        gl::FLOAT_VEC4 => shader.set_uniform(name, &<[f32; 4]>::try_from(numbers(4)?).unwrap()),
+        gl::FLOAT_MAT3x2 => shader.set_uniform(name, &<[f32; 6]>::try_from(numbers(6)?).unwrap()),
+        gl::FLOAT_MAT3 => shader.set_uniform(name, &<[f32; 9]>::try_from(numbers(9)?).unwrap()),
+        gl::FLOAT_MAT4 => shader.set_uniform(name, &<[f32; 16]>::try_from(numbers(16)?).unwrap()),
+        gl::BOOL => shader.set_uniform(name, &(value.as_boolean().ok_or_else(|| format!("Uniform \"{}\" expects a boolean", name))?)),
+        gl::UNSIGNED_INT => shader.set_uniform(name, &(value.as_u32().ok_or_else(|| format!("Uniform \"{}\" expects an unsigned integer", name))?)),
+        x if x == gl::INT || uniform::is_sampler(x) => shader.set_uniform(name, &(value.as_i32().ok_or_else(|| format!("Uniform \"{}\" expects an integer", name))?)),
+        x => Err(format!("Uniform \"{}\" has unsupported type {}", name, uniform::type_name(x))),
+    }
+}
+
+/// Overrides `base` with the options present, `filter` sets both filters, `wrap` both axes, the specific keys override them
+fn sampler_desc_from_table(o: &LuaTable, base: SamplerDesc) -> LuaResult<SamplerDesc> {
+    fn parse<T>(o: &LuaTable, key: &str, from_name: fn(&str) -> Option<T>) -> LuaResult<Option<T>> {
+        match o.get::<Option<String>>(key)? {
+            Some(name) => from_name(&name)
+                .map(Some)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Invalid {} \"{}\"", key, name))),
+            None => Ok(None),
+        }
+    }
+    let mut desc = base;
+    if let Some(x) = parse(o, "filter", Filter::from_name)? {
+        desc.min_filter = x;
+        desc.mag_filter = x;
+    }
+    if let Some(x) = parse(o, "wrap", WrapMode::from_name)? {
+        desc.wrap_s = x;
+        desc.wrap_t = x;
+    }
+    desc.min_filter = parse(o, "min_filter", Filter::from_name)?.unwrap_or(desc.min_filter);
+    desc.mag_filter = parse(o, "mag_filter", Filter::from_name)?.unwrap_or(desc.mag_filter);
+    desc.mipmap_mode = parse(o, "mipmaps", MipmapMode::from_name)?.unwrap_or(desc.mipmap_mode);
+    desc.wrap_s = parse(o, "wrap_s", WrapMode::from_name)?.unwrap_or(desc.wrap_s);
+    desc.wrap_t = parse(o, "wrap_t", WrapMode::from_name)?.unwrap_or(desc.wrap_t);
+    desc.anisotropy = o.get::<Option<f32>>("anisotropy")?.unwrap_or(desc.anisotropy);
+    if let Some(c) = o.get::<Option<LuaTable>>("border_color")? {
+        let get = |key: &str| c.get::<Option<f32>>(key).ok().flatten().unwrap_or(0.0);
+        desc.border_color = [get("r"), get("g"), get("b"), get("a")];
+    }
+    Ok(desc)
+}
+fn play_mode_from_table(o: &LuaTable) -> LuaResult<PlayMode> {
+    match o.get::<Option<String>>("mode")? {
+        Some(name) => PlayMode::from_name(&name).ok_or_else(|| LuaError::RuntimeError(format!("Unknown play mode \"{}\"", name))),
+        None => Ok(PlayMode::Loop),
+    }
+}
+fn blend_mode_from_name(name: &str) -> LuaResult<BlendMode> {
+    BlendMode::from_name(name).ok_or_else(|| LuaError::RuntimeError(format!("Unknown blend mode \"{}\"", name)))
+}
+/// `{NAME = true, VALUE = 3}` to `#define NAME` / `#define VALUE 3`
+fn defines_from_table(table: Option<LuaTable>) -> LuaResult<Vec<(String, String)>> {
+    let mut defines = Vec::new();
+    if let Some(table) = table {
+        for pair in table.pairs::<String, LuaValue>() {
+            let (name, value) = pair?;
+            match value {
+                LuaValue::Boolean(true) => defines.push((name, String::new())),
+                LuaValue::Boolean(false) => {}
+                LuaValue::Integer(x) => defines.push((name, x.to_string())),
+                LuaValue::Number(x) => defines.push((name, x.to_string())),
+                LuaValue::String(x) => defines.push((name, x.to_str()?.to_string())),
+                _ => return Err(LuaError::RuntimeError(format!("Define \"{}\" must be a boolean, number or string", name))),
+            }
+        }
+    }
+    Ok(defines)
+}
+
+fn texture_options_from_table(o: Option<&LuaTable>) -> LuaResult<(bool, SamplerDesc)> {
+    match o {
+        Some(o) => Ok((o.get::<Option<bool>>("srgb")?.unwrap_or(false), sampler_desc_from_table(o, SamplerDesc::default())?)),
+        None => Ok((false, SamplerDesc::default())),
+    }
+}
+/// Images are either a list of paths named after their file stem, or a name = path table
+fn atlas_files_from_table(images: &LuaTable, o: Option<&LuaTable>) -> LuaResult<(Vec<(String, String)>, AtlasOptions)> {
+    let mut files = Vec::new();
+    for pair in images.pairs::<LuaValue, String>() {
+        let (key, path) = pair?;
+        let name = match key {
+            LuaValue::String(name) => name.to_str()?.to_string(),
+            _ => atlas::region_name(&path)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Invalid atlas image path \"{}\"", path)))?,
+        };
+        files.push((name, path));
+    }
+    // Sorted so the layout doesn't depend on table iteration order
+    files.sort();
+    let mut options = AtlasOptions::default();
+    if let Some(o) = o {
+        options.padding = o.get::<Option<u32>>("padding")?.unwrap_or(options.padding);
+        options.max_size = o.get::<Option<u32>>("max_size")?.unwrap_or(options.max_size);
+        options.srgb = o.get::<Option<bool>>("srgb")?.unwrap_or(options.srgb);
+        options.sampler = sampler_desc_from_table(o, options.sampler)?;
+    }
+    Ok((files, options))
+}
+
+fn sprite_instance_from_table(o: &LuaTable) -> SpriteInstance {
+    let get = |key: &str, default: f32| o.get::<Option<f32>>(key).ok().flatten().unwrap_or(default);
+    let mut transform = Transform2D::new(
+        Vec2::new(get("x", 0.0), get("y", 0.0)),
+        Vec2::new(get("scale_x", 1.0), get("scale_y", 1.0)),
+        get("rotation", 0.0),
+    );
+    let mut instance = SpriteInstance::new(transform.transformation_matrix());
+    instance.uv_rect = [get("u", 0.0), get("v", 0.0), get("uv_width", 1.0), get("uv_height", 1.0)];
+    instance.color = [get("r", 1.0), get("g", 1.0), get("b", 1.0), get("a", 1.0)];
+    instance
+}
+/// `draw_sprite` options, u/v/uv_width/uv_height are relative to the atlas region.
+/// Without `blend`, textures without alpha drawn with a = 1 are opaque and batched by state
+fn sprite_from_table(key: &str, o: Option<&LuaTable>) -> LuaResult<Sprite> {
+    let mut sprite = Sprite::new(key);
+    if let Some(o) = o {
+        sprite.instance = sprite_instance_from_table(o);
+        if let Ok(Some(shader)) = o.get::<Option<String>>("shader") {
+            sprite.set_shader(&shader);
+        }
+        sprite.set_sampler(o.get::<Option<String>>("sampler").ok().flatten().as_deref());
+        if let Ok(Some(name)) = o.get::<Option<String>>("blend") {
+            sprite.blend = Some(blend_mode_from_name(&name)?);
+        }
+    }
+    Ok(sprite)
+}
+/// Lua owned animator. `update` advances it and calls the `on_finished` callback with the animator and the clip name,
+/// once attached to a scene node the scene does both instead
+struct LuaAnimator {
+    /// Shared with the scene nodes it's attached to
+    animator: SharedAnimator,
+    on_finished: Option<LuaFunction>,
+    resource_manager: Rc<RefCell<ResourceManager>>,
+}
+impl LuaUserData for LuaAnimator {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("add_grid_clip", |_, this, x: (String, String, LuaTable)| {
+            let columns = x.2.get::<Option<u32>>("columns")?.unwrap_or(1).max(1);
+            let rows = x.2.get::<Option<u32>>("rows")?.unwrap_or(1).max(1);
+            let first = x.2.get::<Option<u32>>("first")?.unwrap_or(0);
+            let count = x.2.get::<Option<u32>>("count")?.unwrap_or((columns * rows).saturating_sub(first));
+            let duration = x.2.get::<Option<f32>>("duration")?.unwrap_or(0.1);
+            let mode = play_mode_from_table(&x.2)?;
+            this.animator.borrow_mut().add_clip(&x.0, AnimationClip::from_grid(&x.1, columns, rows, first, count, duration, mode));
+            Ok(())
+        });
+        methods.add_method_mut("add_sprite_clip", |_, this, x: (String, Vec<String>, Option<LuaTable>)| {
+            let duration = x.2.as_ref().map(|o| o.get::<Option<f32>>("duration")).transpose()?.flatten().unwrap_or(0.1);
+            let mode = match &x.2 {
+                Some(o) => play_mode_from_table(o)?,
+                None => PlayMode::Loop,
+            };
+            this.animator.borrow_mut().add_clip(&x.0, AnimationClip::from_sprites(&x.1, duration, mode));
+            Ok(())
+        });
+        methods.add_method_mut("load_aseprite", |_, this, x: (String, String)| {
+            let clips = animation::load_aseprite(&x.0, &x.1).map_err(LuaError::RuntimeError)?;
+            let mut names: Vec<String> = clips.keys().cloned().collect();
+            names.sort();
+            for (name, clip) in clips {
+                this.animator.borrow_mut().add_clip(&name, clip);
+            }
+            Ok(names)
+        });
+        methods.add_method_mut("play", |_, this, x: (String, Option<bool>)| {
+            this.animator.borrow_mut().play(&x.0, x.1.unwrap_or(false)).map_err(LuaError::RuntimeError)
+        });
+        methods.add_method_mut("stop", |_, this, ()| {
+            this.animator.borrow_mut().stop();
+            Ok(())
+        });
+        methods.add_method_mut("set_speed", |_, this, speed: f32| {
+            this.animator.borrow_mut().speed = speed;
+            Ok(())
+        });
+        methods.add_method("is_playing", |_, this, ()| Ok(this.animator.borrow().is_playing()));
+        methods.add_method("clip", |_, this, ()| Ok(this.animator.borrow().clip().map(|x| x.to_string())));
+        // 1-based like the rest of the Lua API
+        methods.add_method("frame", |_, this, ()| Ok(this.animator.borrow().frame_index() + 1));
+        methods.add_method_mut("on_finished", |_, this, callback: Option<LuaFunction>| {
+            this.on_finished = callback;
+            Ok(())
+        });
+        // The callback may use the animator, so it runs after the borrow for advancing ends
+        methods.add_function("update", |_, x: (LuaAnyUserData, f32)| {
+            let (finished, callback) = {
+                let this = x.0.borrow::<LuaAnimator>()?;
+                let mut animator = this.animator.borrow_mut();
+                animator.advance(x.1);
+                (animator.take_finished(), this.on_finished.clone())
+            };
+            if let Some(callback) = callback {
+                for clip in finished {
+                    callback.call::<()>((x.0.clone(), clip))?;
+                }
+            }
+            Ok(())
+        });
+        methods.add_method("draw", |_, this, o: Option<LuaTable>| {
+            let instance = match &o {
+                Some(o) => sprite_instance_from_table(o),
+                None => SpriteInstance::new(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0]),
+            };
+            this.animator.borrow().draw(&mut this.resource_manager.borrow_mut(), instance);
+            Ok(())
+        });
+        methods.add_method_mut("set_shader", |_, this, shader: String| {
+            this.animator.borrow_mut().set_shader(&shader);
+            Ok(())
+        });
+        methods.add_method_mut("set_blend_mode", |_, this, name: String| {
+            this.animator.borrow_mut().blend = blend_mode_from_name(&name)?;
+            Ok(())
+        });
+    }
+}
+
+/// Lua handle to a scene entity, methods fail once it has been removed
+#[derive(Clone)]
+struct LuaSceneNode {
+    id: ContainerId,
+    scene: Rc<RefCell<Scene2D>>,
+}
+impl LuaSceneNode {
+    fn removed(&self) -> LuaError {
+        LuaError::RuntimeError(format!("Scene node {:?} was removed", self.id))
+    }
+    fn with_transform<R>(&self, f: impl FnOnce(&mut Transform2D) -> R) -> LuaResult<R> {
+        let scene = self.scene.borrow();
+        let mut transform = scene.world().get_mut::<Transform2D>(self.id).ok_or_else(|| self.removed())?;
+        Ok(f(&mut transform))
+    }
+    fn node(&self, id: ContainerId) -> LuaSceneNode {
+        LuaSceneNode { id, scene: Rc::clone(&self.scene) }
+    }
+    /// Adds, or with None removes, a component
+    fn set_component<T: 'static>(&self, component: Option<T>) -> LuaResult<()> {
+        let mut scene = self.scene.borrow_mut();
+        if !scene.world().is_alive(self.id) {
+            return Err(self.removed());
+        }
+        match component {
+            Some(x) => { scene.world_mut().insert(self.id, x); }
+            None => { scene.world_mut().remove::<T>(self.id); }
+        }
+        Ok(())
+    }
+}
+impl LuaUserData for LuaSceneNode {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("add_child", |_, this, ()| {
+            let id = this.scene.borrow_mut().add_child(this.id, Transform2D::default()).map_err(LuaError::RuntimeError)?;
+            Ok(this.node(id))
+        });
+        // nil makes the node a root
+        methods.add_method("set_parent", |_, this, parent: Option<LuaUserDataRef<LuaSceneNode>>| {
+            this.scene.borrow_mut().set_parent(this.id, parent.map(|x| x.id)).map_err(LuaError::RuntimeError)
+        });
+        methods.add_method("parent", |_, this, ()| {
+            let scene = this.scene.borrow();
+            let container = scene.world().get::<Container2D>(this.id).ok_or_else(|| this.removed())?;
+            Ok(container.parent().map(|id| this.node(id)))
+        });
+        methods.add_method("children", |_, this, ()| {
+            let scene = this.scene.borrow();
+            let container = scene.world().get::<Container2D>(this.id).ok_or_else(|| this.removed())?;
+            Ok(container.children().iter().map(|id| this.node(*id)).collect::<Vec<_>>())
+        });
+        // Removes the children too, false if it was already removed
+        methods.add_method("remove", |_, this, ()| Ok(this.scene.borrow_mut().remove(this.id)));
+        methods.add_method("is_valid", |_, this, ()| Ok(this.scene.borrow().world().is_alive(this.id)));
+        methods.add_method("set_position", |_, this, x: (f32, f32)| this.with_transform(|t| t.set_position(Vec2::new(x.0, x.1))));
+        methods.add_method("position", |_, this, ()| this.with_transform(|t| (t.position().x, t.position().y)));
+        methods.add_method("set_scale", |_, this, x: (f32, f32)| this.with_transform(|t| t.set_scale(Vec2::new(x.0, x.1))));
+        methods.add_method("set_rotation", |_, this, radians: f32| this.with_transform(|t| t.set_radians(radians)));
+        methods.add_method("rotation", |_, this, ()| this.with_transform(|t| t.radians()));
+        // As of the last scene update
+        methods.add_method("world_position", |_, this, ()| {
+            let scene = this.scene.borrow();
+            let world_transform = scene.world().get::<WorldTransform>(this.id).ok_or_else(|| this.removed())?;
+            Ok((world_transform.matrix[4], world_transform.matrix[5]))
+        });
+        // Same options as draw_sprite, the position comes from the node. nil removes the sprite
+        methods.add_method("set_sprite", |_, this, x: (Option<String>, Option<LuaTable>)| {
+            let sprite = x.0.map(|key| sprite_from_table(&key, x.1.as_ref())).transpose()?;
+            this.set_component(sprite)
+        });
+        // Called with the node and the delta before the scene updates, nil removes the script
+        methods.add_method("set_script", |_, this, function: Option<LuaFunction>| {
+            this.set_component(function.map(LuaScript))
+        });
+        // The scene advances and draws the animator at the node from now on, nil detaches it
+        methods.add_method("attach_animator", |_, this, animator: Option<LuaAnyUserData>| {
+            let shared = animator.as_ref().map(|x| x.borrow::<LuaAnimator>().map(|x| Rc::clone(&x.animator))).transpose()?;
+            this.set_component(shared)?;
+            this.set_component(animator.map(AnimatorOwner))
+        });
+    }
+}
+
+/// Script component
+struct LuaScript(LuaFunction);
+
+/// Lua handle of an attached animator, for its `on_finished` callback
+struct AnimatorOwner(LuaAnyUserData);
+
+/// Calls the `on_finished` callbacks of the clips attached animators finished during the last scene update
+pub fn run_animator_callbacks(scene: &Rc<RefCell<Scene2D>>) {
+    let finished = scene.borrow().finished_clips().to_vec();
+    for (id, clip) in finished {
+        // An earlier callback may have removed the node or detached the animator
+        let Some(owner) = scene.borrow().world().get::<AnimatorOwner>(id).map(|x| x.0.clone()) else {
+            continue;
+        };
+        let callback = owner.borrow::<LuaAnimator>().ok().and_then(|x| x.on_finished.clone());
+        if let Some(callback) = callback {
+            if let Err(e) = callback.call::<()>((owner, clip)) {
+                println!("Animation callback error:{}", e);
+            }
+        }
+    }
+}
+
+/// Calls the node scripts. They run outside the scene borrow, so they can change the scene
+pub fn run_scripts(scene: &Rc<RefCell<Scene2D>>, delta: f32) {
+    let mut scripts = Vec::new();
+    scene.borrow().world().query::<LuaScript>(|id, script| scripts.push((id, script.0.clone())));
+    for (id, function) in scripts {
+        // An earlier script may have removed the node
+        if !scene.borrow().world().is_alive(id) {
+            continue;
+        }
+        let node = LuaSceneNode { id, scene: Rc::clone(scene) };
+        if let Err(e) = function.call::<()>((node, delta)) {
+            println!("Node script error:{}", e);
+        }
+    }
+}
+
+pub fn bind_lua(lua :&Lua, resource_manager : &Rc<RefCell<ResourceManager>>, scene: &Rc<RefCell<Scene2D>>){
     
+    let manager = Rc::clone(resource_manager);
     lua.globals().set("material_load_shader", lua.create_function_mut(move |_: &Lua, x: (String, String, String)| {
-        let s = Box::new(Shader::create_new(&x.1,&x.2)); 
+        let mut rm = manager.borrow_mut();
+        // Rebuild in place so a failed reload keeps the working program
+        if let Some(s) = rm.get_resource_mut::<Shader>(&x.0) {
+            s.create(&x.1, &x.2).map_err(|e| LuaError::RuntimeError(format!("Shader \"{}\": {}", x.0, e)))?;
+            s.bind_ubo("Matrices", Camera::MATRICES_BINDING_POINT);
+            rm.set_source(&x.0, None);
+            return Ok(());
+        }
+        let s = Shader::create_new(&x.1,&x.2).map_err(|e| LuaError::RuntimeError(format!("Shader \"{}\": {}", x.0, e)))?;
         s.bind_ubo("Matrices", Camera::MATRICES_BINDING_POINT);
-        resource_manager_clone.borrow_mut().add_resource(&x.0, s);
+        rm.add_resource(&x.0, s);
+        rm.set_source(&x.0, None);
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_shader_variant", lua.create_function_mut(move |_: &Lua, x: (String, String, String, Option<LuaTable>)| {
+        let files = ShaderFiles::Separate { vertex: x.1, fragment: x.2 };
+        manager.borrow_mut().load_shader(&x.0, files, defines_from_table(x.3)?).map_err(LuaError::RuntimeError)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_shader_file", lua.create_function_mut(move |_: &Lua, x: (String, String, Option<LuaTable>)| {
+        manager.borrow_mut().load_shader(&x.0, ShaderFiles::Single(x.1), defines_from_table(x.2)?).map_err(LuaError::RuntimeError)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_shader_file_async", lua.create_function_mut(move |_: &Lua, x: (String, String, Option<LuaTable>)| {
+        let request = AssetRequest::Shader { files: ShaderFiles::Single(x.1), defines: defines_from_table(x.2)? };
+        manager.borrow_mut().load_async(&x.0, request);
         Ok(())
     }).unwrap()).unwrap();
-    lua.globals().set("material_load_mesh", lua.create_function_mut(move |_: &Lua, x: (String, LuaTable)| {
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_mesh", lua.create_function_mut(move |_: &Lua, x: (String, LuaTable, Option<String>, Option<Vec<u32>>)| {
         let mesh_table: LuaTable = x.1;
         
         // Convert LuaTable to Vec of PlanarTextureVertex
-        let mesh: Vec<PlanarTextureVertex> = mesh_table.pairs::<String, LuaTable>()
-            .map(|pair| {
-                let vertex_table = pair.unwrap().1;
+        let mesh: Vec<PlanarTextureVertex> = mesh_table.sequence_values::<LuaTable>()
+            .enumerate()
+            .map(|(i, vertex_table)| {
+                let vertex_table = vertex_table.map_err(|_| LuaError::RuntimeError(format!("Mesh \"{}\": vertex {} is not a table", x.0, i + 1)))?;
                 let x: f32 = vertex_table.get::< f32>("x").unwrap_or(0.0);
                 let y: f32 = vertex_table.get::<f32>("y").unwrap_or(0.0);
                 let tx: f32 = vertex_table.get::<f32>("tx").unwrap_or(0.0);
                 let ty: f32 = vertex_table.get::<f32>("ty").unwrap_or(0.0);
                 
-                PlanarTextureVertex::new(x, y, tx, ty)
+                Ok(PlanarTextureVertex::new(x, y, tx, ty))
             })
-            .collect();
-    
-        let mut s = Box::new(Mesh::new());
-        s.create::<PlanarTextureVertex,u8>(&mesh, None);
-        resource_manager_clone_2.borrow_mut().add_resource(&x.0, s);
+            .collect::<LuaResult<_>>()?;
+
+        let topology = match x.2.as_deref() {
+            Some(name) => Topology::from_name(name)
+                .ok_or_else(|| LuaError::RuntimeError(format!("Unknown topology \"{}\"", name)))?,
+            None => Topology::TriangleStrip,
+        };
+        let mut s = Mesh::new();
+        if let Some(i) = x.3.iter().flatten().find(|&&i| i == 0 || i as usize > mesh.len()) {
+            return Err(LuaError::RuntimeError(format!("Index {} is out of range for {} vertices", i, mesh.len())));
+        }
+        // Lua indices start at 1
+        let indices: Option<Vec<u32>> = x.3.map(|x| x.iter().map(|&i| i - 1).collect());
+        s.create_planar(topology, &mesh, indices.as_deref()).map_err(LuaError::RuntimeError)?;
+        manager.borrow_mut().add_resource(&x.0, s);
         Ok(())
     }).unwrap()).unwrap();
-    lua.globals().set("material_load_texture", lua.create_function_mut(move |_: &Lua, x: (String, String)| {
-        let path = x.1.clone();
-        if let Ok(texture) = image::open(path){
-            let mut t = Box::new(Texture::create_new(gl::TEXTURE_2D));
-            t.set_texture_rgb(&texture.flipv().to_rgb8());
-            resource_manager_clone_3.borrow_mut().add_resource(&x.0, t);
-        }else {
-            println!("File \"{}\" is not found or not a image",x.1);
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_texture", lua.create_function_mut(move |_: &Lua, x: (String, String, Option<LuaTable>)| {
+        let (srgb, sampler) = texture_options_from_table(x.2.as_ref())?;
+        manager.borrow_mut().load_texture(&x.0, &x.1, srgb, sampler).map_err(LuaError::RuntimeError)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("material_load_texture_async", lua.create_function_mut(move |_: &Lua, x: (String, String, Option<LuaTable>)| {
+        let (srgb, sampler) = texture_options_from_table(x.2.as_ref())?;
+        manager.borrow_mut().load_async(&x.0, AssetRequest::Texture { path: x.1, srgb, sampler });
+        Ok(())
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("sampler_create", lua.create_function_mut(move |_: &Lua, x: (String, LuaTable)| {
+        let s = Sampler::create_new(&sampler_desc_from_table(&x.1, SamplerDesc::default())?);
+        manager.borrow_mut().add_resource(&x.0, s);
+        Ok(())
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("atlas_load", lua.create_function_mut(move |_: &Lua, x: (String, LuaTable, Option<LuaTable>)| {
+        let (files, options) = atlas_files_from_table(&x.1, x.2.as_ref())?;
+        manager.borrow_mut().load_atlas(&x.0, files, options).map_err(LuaError::RuntimeError)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("atlas_load_async", lua.create_function_mut(move |_: &Lua, x: (String, LuaTable, Option<LuaTable>)| {
+        let (files, options) = atlas_files_from_table(&x.1, x.2.as_ref())?;
+        manager.borrow_mut().load_async(&x.0, AssetRequest::Atlas { files, options });
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("load_manifest", lua.create_function_mut(move |_: &Lua, path: String| {
+        let errors = manager.borrow_mut().load_manifest(&path);
+        for e in &errors {
+            println!("{}", e);
         }
+        Ok(errors)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("resource_group", lua.create_function_mut(move |_: &Lua, group: String| {
+        manager.borrow_mut().set_group(&group);
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("unload_group", lua.create_function_mut(move |_: &Lua, group: String| {
+        manager.borrow_mut().unload_group(&group);
         Ok(())
     }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("resource_acquire", lua.create_function_mut(move |_: &Lua, key: String| {
+        Ok(manager.borrow_mut().acquire(&key))
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("resource_release", lua.create_function_mut(move |_: &Lua, key: String| {
+        manager.borrow_mut().release(&key);
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("loading_progress", lua.create_function_mut(move |_: &Lua, ()| {
+        Ok(manager.borrow().loading_progress())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("atlas_region", lua.create_function_mut(move |lua: &Lua, key: String| {
+        let rm = manager.borrow();
+        let Some((_, region)) = rm.atlas_region(&key) else {
+            return Ok(LuaValue::Nil);
+        };
+        let result = lua.create_table()?;
+        result.set("x", region.x)?;
+        result.set("y", region.y)?;
+        result.set("width", region.width)?;
+        result.set("height", region.height)?;
+        result.set("u", region.uv_rect[0])?;
+        result.set("v", region.uv_rect[1])?;
+        result.set("uv_width", region.uv_rect[2])?;
+        result.set("uv_height", region.uv_rect[3])?;
+        Ok(LuaValue::Table(result))
+    }).unwrap()).unwrap();
 
-    lua.globals().set("read_file", lua.create_function(|_: &Lua, path: String| {
-        let mut buf1 = String::new();
-        if let Ok(mut a) = File::open(path) {
-            let _ = a.read_to_string(&mut buf1);
+    let scene = Rc::clone(scene);
+    lua.globals().set("scene_create_node", lua.create_function(move |_: &Lua, ()| {
+        let id = scene.borrow_mut().add(Transform2D::default());
+        Ok(LuaSceneNode { id, scene: Rc::clone(&scene) })
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("animator_create", lua.create_function_mut(move |_: &Lua, ()| {
+        Ok(LuaAnimator {
+            animator: Rc::new(RefCell::new(Animator::new())),
+            on_finished: None,
+            resource_manager: Rc::clone(&manager),
+        })
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("render_target_create", lua.create_function_mut(move |_: &Lua, x: (String, i32, i32, Option<LuaTable>)| {
+        let depth = x.3.as_ref().and_then(|o| o.get::<bool>("depth").ok()).unwrap_or(false);
+        let follow_window = x.3.as_ref().and_then(|o| o.get::<bool>("follow_window").ok()).unwrap_or(false);
+        let mut t = RenderTarget::create_new(x.1, x.2, depth).map_err(LuaError::RuntimeError)?;
+        t.set_follow_window(follow_window);
+        manager.borrow_mut().add_resource(&x.0, t);
+        Ok(())
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("render_target_bind", lua.create_function(move |_: &Lua, name: String| {
+        manager.borrow_mut().bind_render_target(&name).map_err(LuaError::RuntimeError)
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("render_target_unbind", lua.create_function(move |_: &Lua, ()| {
+        Ok(manager.borrow_mut().unbind_render_target())
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("render_target_size", lua.create_function(move |_: &Lua, name: String| {
+        match manager.borrow().get_resource::<RenderTarget>(&name) {
+            Some(target) => Ok(target.size()),
+            None => Err(LuaError::RuntimeError(format!("Render target \"{}\" not found", name))),
         }
-        return Ok(buf1);
     }).unwrap()).unwrap();
 
+    lua.globals().set("read_file", lua.create_function(|_: &Lua, path: String| {
+        vfs::read_to_string(&path).map_err(|e| LuaError::RuntimeError(format!("Failed to read \"{}\": {}", path, e)))
+    }).unwrap()).unwrap();
+
+    lua.globals().set("vfs_mount", lua.create_function(|_: &Lua, x: (String, Option<String>)| {
+        vfs::mount(&x.0, x.1.as_deref().unwrap_or("")).map_err(|e| LuaError::RuntimeError(format!("Failed to mount \"{}\": {}", x.0, e)))
+    }).unwrap()).unwrap();
+
+    lua.globals().set("vfs_unmount", lua.create_function(|_: &Lua, path: String| {
+        Ok(vfs::unmount(&path))
+    }).unwrap()).unwrap();
+
+    let manager = Rc::clone(resource_manager);
     lua.globals().set("get_camera_position", lua.create_function_mut(move |lua: &Lua, ()| {
         // Create a Lua table representing a vector
-        let rm = resource_manager_clone_4.borrow();
+        let rm = manager.borrow();
         let a = rm.camera();
         let vec_table = lua.create_table()?;
         vec_table.set("x", a.position().x)?; // x component
         vec_table.set("y", a.position().y)?; // y component
         Ok(vec_table)
     }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
     lua.globals().set("set_camera_position", lua.create_function_mut(move |_: &Lua, x: LuaTable| {
         let x_ = x.get("x").unwrap_or(0.0);
         let y_ = x.get("y").unwrap_or(0.0);
-        let mut rm = resource_manager_clone_5.borrow_mut();
+        let mut rm = manager.borrow_mut();
         rm.camera_mut().set_position(Vec2::new(x_,y_));
         Ok(())
     }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("capture_frame", lua.create_function_mut(move |_: &Lua, path: String| {
+        manager.borrow_mut().request_capture(&path);
+        Ok(())
+    }).unwrap()).unwrap();
+    lua.globals().set("compare_images", lua.create_function(|lua: &Lua, x: (String, String, Option<u8>, Option<String>)| {
+        let diff = capture::compare_files(&x.0, &x.1, x.2.unwrap_or(0), x.3.as_deref())
+            .map_err(LuaError::RuntimeError)?;
+        let result = lua.create_table()?;
+        result.set("matches", diff.matches())?;
+        result.set("mismatched_pixels", diff.mismatched_pixels)?;
+        result.set("max_channel_delta", diff.max_channel_delta)?;
+        Ok(result)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("draw_sprite", lua.create_function_mut(move |_: &Lua, x: (String, Option<LuaTable>)| {
+        let mut sprite = sprite_from_table(&x.0, x.1.as_ref())?;
+        let mut rm = manager.borrow_mut();
+        // Resources that aren't loaded (yet) skip the sprite, the result tells the script
+        let Some((state, instance)) = sprite.resolve(&rm) else {
+            return Ok(false);
+        };
+        rm.sprite_batch_mut().draw(state, instance);
+        Ok(true)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("set_blend_mode", lua.create_function_mut(move |_: &Lua, name: String| {
+        manager.borrow_mut().set_blend_mode(blend_mode_from_name(&name)?);
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("draw_mesh_instanced", lua.create_function_mut(move |_: &Lua, x: (String, String, String, LuaTable)| {
+        let instances: Vec<SpriteInstance> = x.3.sequence_values::<LuaTable>()
+            .enumerate()
+            .map(|(i, t)| {
+                let t = t.map_err(|_| LuaError::RuntimeError(format!("Mesh \"{}\": instance {} is not a table", x.0, i + 1)))?;
+                Ok(sprite_instance_from_table(&t))
+            })
+            .collect::<LuaResult<_>>()?;
+        let mut rm = manager.borrow_mut();
+        let shader = rm.handle::<Shader>(&x.1)
+            .ok_or_else(|| LuaError::RuntimeError(format!("Shader \"{}\" not found", x.1)))?;
+        let mesh = rm.handle::<Mesh>(&x.0)
+            .ok_or_else(|| LuaError::RuntimeError(format!("Mesh \"{}\" not found", x.0)))?;
+        // Sprites queued before this call are drawn first, so they stay behind the mesh
+        rm.flush_sprites();
+        rm.get(shader).unwrap().bind();
+        if let Some(texture) = rm.texture_handle(&x.2).and_then(|x| rm.texture(x)) {
+            texture.bind(gl::TEXTURE0);
+        }
+        let mesh = rm.get_mut(mesh).unwrap();
+        mesh.set_instances(&instances, SpriteInstance::BASE_LOCATION, gl::STREAM_DRAW);
+        mesh.draw_instanced(instances.len() as i32);
+        Ok(())
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("sprite_batch_stats", lua.create_function(move |lua: &Lua, ()| {
+        let stats = manager.borrow().sprite_batch().stats();
+        let result = lua.create_table()?;
+        result.set("draw_calls", stats.draw_calls)?;
+        result.set("sprites", stats.sprites)?;
+        result.set("vertices", stats.vertices)?;
+        Ok(result)
+    }).unwrap()).unwrap();
+    let manager = Rc::clone(resource_manager);
+    lua.globals().set("get_shader", lua.create_function(move |_: &Lua, key: String| {
+        Ok(LuaShader { key, resource_manager: Rc::clone(&manager) })
+    }).unwrap()).unwrap();
 }
\ No newline at end of file
@@ -2,34 +2,25 @@ use std::rc::Rc;
 use std::cell::RefCell;
 
 use glfw::{Action, Context, Key};
-use lua_bindings::{bind_lua, reload_and_execute_script};
-use mesh::Mesh;
 use mlua::Lua;
-use resource_manager::ResourceManager;
-use shader::Shader;
-use texture::Texture;
-use transform::Transform2D;
-mod buffers;
-mod shader;
-mod texture;
-mod mesh;
-mod lua_bindings;
-mod camera;
-mod transform;
-mod resource_manager;
-mod container;
+use rgms::atlas::AtlasRegion;
+use rgms::buffers::FramebufferObject;
+use rgms::container::{ContainerId, Scene2D};
+use rgms::lua_bindings::{bind_lua, reload_and_execute_script, run_animator_callbacks, run_scripts};
+use rgms::handle::Handle;
+use rgms::mesh::Mesh;
+use rgms::render_target::RenderTarget;
+use rgms::resource_manager::{ResourceManager, TextureHandle};
+use rgms::shader::Shader;
+use rgms::transform::Transform2D;
+use rgms::headless::HeadlessContext;
+use rgms::{capture, hot_reload, vfs};
 use mlua::prelude::*;
 
 fn error_callback(err: glfw::Error, description: String) {
-    panic!("GLFW error {:?}: {:?}", err, description);
+    println!("GLFW error {:?}: {:?}", err, description);
 }
 
-/*
-unsafe {
-    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-    gl::Enable( gl::BLEND );
-    //gl::Enable(gl::CULL_FACE);
-}*/
 /*
     let mut tex = Box::new(Texture::new(gl::TEXTURE_2D));
     tex.create();
@@ -37,68 +28,373 @@ unsafe {
     for (x, y, pixel) in id.enumerate_pixels_mut() {
         *pixel = Rgb([x as u8, y as u8, 0]);
     }
-    tex.set_texture_rgb(&id);
+    tex.set_image(&TextureImage::from_image(DynamicImage::ImageRgb8(id), false));
     resource_manager.borrow_mut().add_resource("default_texture",  tex);
 */
+struct LaunchOptions {
+    /// Renders offscreen through EGL's surfaceless platform, so no X or Wayland display is needed.
+    /// In CI with Mesa's llvmpipe: `rgms --headless --frames 10 --golden golden.png --tolerance 2`
+    headless: bool,
+    frames: u32,
+    width: u32,
+    height: u32,
+    context_api: glfw::ContextCreationApi,
+    capture: Option<String>,
+    golden: Option<String>,
+    tolerance: u8,
+    diff: Option<String>,
+    /// (path, mount point) in priority order, lowest first
+    mounts: Vec<(String, String)>,
+}
+
+fn parse_args() -> LaunchOptions {
+    let mut options = LaunchOptions {
+        headless: false,
+        frames: 1,
+        width: 800,
+        height: 600,
+        context_api: glfw::ContextCreationApi::Native,
+        capture: None,
+        golden: None,
+        tolerance: 0,
+        diff: None,
+        mounts: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = args.next().and_then(|x| x.parse().ok()).unwrap_or(options.frames),
+            "--width" => options.width = args.next().and_then(|x| x.parse().ok()).unwrap_or(options.width),
+            "--height" => options.height = args.next().and_then(|x| x.parse().ok()).unwrap_or(options.height),
+            "--capture" => options.capture = args.next(),
+            "--golden" => options.golden = args.next(),
+            "--tolerance" => options.tolerance = args.next().and_then(|x| x.parse().ok()).unwrap_or(options.tolerance),
+            "--diff" => options.diff = args.next(),
+            // --mount mods/hd.zip or --mount mods/hd=resources
+            "--mount" => match args.next() {
+                Some(x) => {
+                    let (path, point) = x.split_once('=').unwrap_or((&x, ""));
+                    options.mounts.push((path.to_string(), point.to_string()));
+                }
+                None => println!("--mount expects a directory or .zip path"),
+            },
+            // Only for the window, headless runs always use EGL's surfaceless platform
+            "--context-api" => {
+                options.context_api = match args.next().as_deref() {
+                    Some("native") => glfw::ContextCreationApi::Native,
+                    Some("egl") => glfw::ContextCreationApi::Egl,
+                    Some("osmesa") => glfw::ContextCreationApi::OsMesa,
+                    x => {
+                        println!("Unknown context api {:?}, using native", x);
+                        glfw::ContextCreationApi::Native
+                    }
+                }
+            }
+            _ => println!("Unknown argument \"{}\"", arg),
+        }
+    }
+    options
+}
+
+const SCRIPT_PATH: &str = "./example.lua";
+const DEFAULT_PACK: &str = "resources.pack";
+/// Time spent uploading asynchronously loaded assets per frame
+const UPLOAD_BUDGET: std::time::Duration = std::time::Duration::from_millis(4);
+
+/// Calls the script's `load`. Resources it doesn't load again are unloaded afterwards,
+/// unchanged ones are kept instead of being loaded twice
+fn load_script(lua: &Lua, resource_manager: &Rc<RefCell<ResourceManager>>, scene: &Rc<RefCell<Scene2D>>) -> bool {
+    let Ok(x) = lua.globals().get::<LuaFunction>("load") else {
+        return false;
+    };
+    // The nodes are built by `load`, the previous ones are only removed once it succeeds
+    // so a failed reload keeps the running scene instead of an empty one
+    let previous: Vec<ContainerId> = scene.borrow().roots().to_vec();
+    resource_manager.borrow_mut().begin_reload();
+    let result = x.call::<()>(());
+    if result.is_ok() {
+        scene.borrow_mut().retain_roots(|id| !previous.contains(&id));
+    } else {
+        scene.borrow_mut().retain_roots(|id| previous.contains(&id));
+    }
+    let mut rm = resource_manager.borrow_mut();
+    rm.end_reload(result.is_ok());
+    rm.shader_cache_mut().prune();
+    if let Err(e) = result {
+        println!("Load script error:{}",e);
+        return false;
+    }
+    true
+}
+
+fn report_leaks(resource_manager: &Rc<RefCell<ResourceManager>>) {
+    for (key, count) in resource_manager.borrow().leaked_resources() {
+        println!("Leaked resource \"{}\", acquired {} more time(s) than released", key, count);
+    }
+}
+
+/// Handles of the resources `render_frame` draws, looked up again only when the resource manager's revision changed
+#[derive(Default)]
+struct FrameResources {
+    revision: Option<u64>,
+    shader: Option<Handle<Shader>>,
+    sprite: Option<(TextureHandle, Option<AtlasRegion>)>,
+    mesh: Option<Handle<Mesh>>,
+}
+
+impl FrameResources {
+    fn update(&mut self, rm: &ResourceManager) {
+        if self.revision == Some(rm.revision()) {
+            return;
+        }
+        self.revision = Some(rm.revision());
+        self.shader = rm.handle("default_shader");
+        self.sprite = rm.resolve_sprite("default_texture");
+        self.mesh = rm.handle("default_quad_mesh_strip");
+    }
+}
+
+fn render_frame(resource_manager: &Rc<RefCell<ResourceManager>>, frame: &mut FrameResources, x: &mut Transform2D) {
+    unsafe {
+        gl::ClearColor(0.2, 0.3, 0.3, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+        // Draw triangle
+        let mut rm = resource_manager.borrow_mut();
+        rm.camera_mut().update();
+        frame.update(&rm);
+        let uv = frame.sprite.and_then(|(_, region)| region).map_or([0.0, 0.0, 1.0, 1.0], |x| x.uv_rect);
+        if let Some(shader) = frame.shader.and_then(|x| rm.get_mut(x)) {
+            shader.bind();
+            let uniforms = shader.set_uniform("transform", x.transformation_matrix())
+                .and_then(|_| shader.set_uniform("sprite_offset", &[uv[0], uv[1]]))
+                .and_then(|_| shader.set_uniform("sprite_size", &[uv[2], uv[3]]));
+            if let Err(e) = uniforms {
+                println!("{}", e);
+            }
+        }
+        if let Some(texture) = frame.sprite.and_then(|(texture, _)| rm.texture(texture)) {
+            texture.bind(gl::TEXTURE0);
+        }
+        if let Some(mesh) = frame.mesh.and_then(|x| rm.get_mut(x)) {
+            mesh.draw();
+        }
+    }
+}
+
+/// Calls the script's optional `update` function and the node scripts, then updates the scene
+/// and reports the finished animations. `delta` is in seconds
+fn update_script(lua: &Lua, scene: &Rc<RefCell<Scene2D>>, delta: f32) {
+    if let Ok(x) = lua.globals().get::<LuaFunction>("update") {
+        if let Err(e) = x.call::<()>(delta) {
+            println!("Update script error:{}",e);
+        }
+    }
+    run_scripts(scene, delta);
+    scene.borrow_mut().update(delta);
+    run_animator_callbacks(scene);
+}
+
+/// Renders the scene, then calls the script's optional `draw` function so it draws on top, and flushes the sprites
+fn draw_script(lua: &Lua, resource_manager: &Rc<RefCell<ResourceManager>>, scene: &Rc<RefCell<Scene2D>>) {
+    resource_manager.borrow_mut().sprite_batch_mut().reset_stats();
+    scene.borrow_mut().render(&mut resource_manager.borrow_mut());
+    if let Ok(x) = lua.globals().get::<LuaFunction>("draw") {
+        if let Err(e) = x.call::<()>(()) {
+            println!("Draw script error:{}",e);
+        }
+    }
+    let mut rm = resource_manager.borrow_mut();
+    // A target the script left bound only gets the sprites queued before the end of `draw`
+    rm.unbind_render_target();
+    rm.flush_sprites();
+}
+
+fn process_captures(resource_manager: &Rc<RefCell<ResourceManager>>) {
+    let requests = resource_manager.borrow_mut().take_capture_requests();
+    for path in requests {
+        if let Err(e) = capture::capture_frame(&path) {
+            println!("{}", e);
+        }
+    }
+}
+
+/// Lua state, resources and scene with the bindings registered, needs a current GL context
+fn create_engine() -> (Lua, Rc<RefCell<ResourceManager>>, Rc<RefCell<Scene2D>>) {
+    let lua: Lua = Lua::new();
+    let resource_manager: Rc<RefCell<ResourceManager>> = Rc::new(RefCell::new(ResourceManager::new()));
+    let scene = Rc::new(RefCell::new(Scene2D::new()));
+    bind_lua(&lua,&resource_manager,&scene);
+    resource_manager.borrow().blend_mode().apply();
+    (lua, resource_manager, scene)
+}
+
+/// Renders `options.frames` frames offscreen, then saves and compares the last one. False on failure
+fn run_headless(options: &LaunchOptions) -> bool {
+    let (lua, resource_manager, scene) = create_engine();
+    let mut x: Transform2D = Transform2D::default();
+    let mut frame = FrameResources::default();
+    let target = match RenderTarget::create_new(options.width as i32, options.height as i32, true) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Failed to create headless render target: {}", e);
+            return false;
+        }
+    };
+
+    if reload_and_execute_script(&lua, SCRIPT_PATH).inspect_err(|e| println!("{}",e)).is_err() || !load_script(&lua, &resource_manager, &scene) {
+        return false;
+    }
+    // Captures should never show placeholders
+    resource_manager.borrow_mut().finish_loading();
+    for _ in 0..options.frames {
+        // Fixed step so captures don't depend on how fast frames render
+        update_script(&lua, &scene, 1.0 / 60.0);
+        target.bind();
+        render_frame(&resource_manager, &mut frame, &mut x);
+        draw_script(&lua, &resource_manager, &scene);
+        process_captures(&resource_manager);
+    }
+    unsafe { gl::Finish() };
+    println!("Rendered {} headless frame(s)", options.frames);
+    report_leaks(&resource_manager);
+
+    let frame = capture::read_framebuffer();
+    FramebufferObject::unbind();
+    if let Some(path) = &options.capture {
+        if let Err(e) = frame.save(path) {
+            println!("Failed to save capture \"{}\": {}", path, e);
+            return false;
+        }
+    }
+    if let Some(path) = &options.golden {
+        let result = image::open(path)
+            .map_err(|e| format!("Failed to open golden image \"{}\": {}", path, e))
+            .and_then(|golden| capture::compare_images(&golden.to_rgba8(), &frame, options.tolerance));
+        match result {
+            Ok(diff) => {
+                if let Some(diff_path) = &options.diff {
+                    if let Err(e) = diff.diff.save(diff_path) {
+                        println!("Failed to save diff \"{}\": {}", diff_path, e);
+                    }
+                }
+                if !diff.matches() {
+                    println!("Golden image mismatch: {} pixel(s) differ, max channel delta {}", diff.mismatched_pixels, diff.max_channel_delta);
+                    return false;
+                }
+            }
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
 fn main() {
-    let mut glfw = glfw::init(error_callback).unwrap();
+    let options = parse_args();
+    // A pack built by rgms-pack has the lowest priority, loose files in the working directory
+    // override it and the --mount packs and mods override both
+    if std::path::Path::new(DEFAULT_PACK).is_file() {
+        if let Err(e) = vfs::mount(DEFAULT_PACK, "") {
+            println!("Failed to mount \"{}\": {}", DEFAULT_PACK, e);
+        }
+    }
+    if let Err(e) = vfs::mount(".", "") {
+        println!("Failed to mount the working directory: {}", e);
+    }
+    for (path, point) in &options.mounts {
+        if let Err(e) = vfs::mount(path, point) {
+            println!("Failed to mount \"{}\": {}", path, e);
+        }
+    }
+    // GLFW needs a display even for hidden windows, so CI machines without X or Wayland
+    // get an EGL surfaceless context instead
+    if options.headless {
+        let context = match HeadlessContext::create() {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Failed to create headless OpenGL context: {}", e);
+                std::process::exit(1);
+            }
+        };
+        gl::load_with(|symbol| context.get_proc_address(symbol));
+        let ok = run_headless(&options);
+        // Every GL object is released by now
+        drop(context);
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut glfw = match glfw::init(error_callback) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Failed to initialize GLFW: {:?}", e);
+            std::process::exit(1);
+        }
+    };
     glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
     glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+    glfw.window_hint(glfw::WindowHint::ContextCreationApi(options.context_api));
     
-    let (mut window, events) = glfw.create_window(800, 600, "OpenGL Triangle",glfw::WindowMode::Windowed)
-    .expect("Failed to create GLFW window");
+    let Some((mut window, events)) = glfw.create_window(options.width, options.height, "OpenGL Triangle",glfw::WindowMode::Windowed) else {
+        println!("Failed to create GLFW window");
+        std::process::exit(1);
+    };
 
     window.make_current();
     window.set_key_polling(true);
+    window.set_framebuffer_size_polling(true);
 
     gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
 
-    let lua: Lua = Lua::new();
-    let resource_manager: Rc<RefCell<ResourceManager>> = Rc::new(RefCell::new(ResourceManager::new()));
-    bind_lua(&lua,&resource_manager);
+    let (lua, resource_manager, scene) = create_engine();
 
     let mut x: Transform2D = Transform2D::default();
+    let mut frame = FrameResources::default();
     
     let mut lua_ok = false;
     let mut lua_loaded = false;
-    if reload_and_execute_script(&lua,"./example.lua").inspect_err(|e| println!("{}",e)).is_ok(){
+    if reload_and_execute_script(&lua,SCRIPT_PATH).inspect_err(|e| println!("{}",e)).is_ok(){
         lua_ok = true;
         lua_loaded = false;
     }
+
+    resource_manager.borrow_mut().enable_hot_reload();
+    resource_manager.borrow_mut().watch_file(std::path::Path::new(SCRIPT_PATH));
+    let script_path = hot_reload::FileWatcher::normalize(std::path::Path::new(SCRIPT_PATH));
+    let mut last_frame = std::time::Instant::now();
+
     while !window.should_close() {
-        if lua_ok {
-            if !lua_loaded{
-                resource_manager.borrow_mut().clear();
-                if let Ok(x) = lua.globals().get::<LuaFunction>("load") {
-                    if let Err(e) =  x.call::<()>(()) {
-                        println!("Load script error:{}",e);
-                    }else {
-                        lua_loaded = true;
-                    }
-                };
-                if !lua_loaded{
-                    lua_ok = false;
+        if lua_ok && !lua_loaded {
+            lua_loaded = load_script(&lua, &resource_manager, &scene);
+            lua_ok = lua_loaded;
+        }
+        let changed = resource_manager.borrow_mut().changed_files();
+        for path in changed {
+            if Some(&path) == script_path.as_ref() || resource_manager.borrow().is_manifest(&path) {
+                if reload_and_execute_script(&lua, SCRIPT_PATH).inspect_err(|e| println!("{}",e)).is_ok() {
+                    lua_loaded = load_script(&lua, &resource_manager, &scene);
+                    lua_ok = lua_loaded;
                 }
+            } else {
+                resource_manager.borrow_mut().reload_file(&path);
             }
         }
-        unsafe {
-            gl::ClearColor(0.2, 0.3, 0.3, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-            // Draw triangle
-            let mut rm = resource_manager.borrow_mut();
-            rm.camera_mut().update();
-            if let Some(shader) = rm.get_resource_mut::<Shader>("default_shader") {
-                shader.bind();
-                shader.set_uniform_mat3x2("transform", x.transformation_matrix());
-            }
-            if let Some(texture) = rm.get_resource_mut::<Texture>("default_texture") {
-                texture.bind(gl::TEXTURE0);
-            }
-            if let Some(mesh) = rm.get_resource_mut::<Mesh>("default_quad_mesh_strip") {
-                mesh.bind();
-            }
-            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        resource_manager.borrow_mut().process_loaded(UPLOAD_BUDGET);
+        let now = std::time::Instant::now();
+        let delta = now.duration_since(last_frame).as_secs_f32();
+        last_frame = now;
+        if lua_loaded {
+            update_script(&lua, &scene, delta);
+        }
+        render_frame(&resource_manager, &mut frame, &mut x);
+        if lua_loaded {
+            draw_script(&lua, &resource_manager, &scene);
         }
+        process_captures(&resource_manager);
 
         window.swap_buffers();
 
@@ -108,8 +404,14 @@ fn main() {
                 glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
                     window.set_should_close(true);
                 }
+                glfw::WindowEvent::FramebufferSize(width, height) => {
+                    unsafe { gl::Viewport(0, 0, width, height) };
+                    resource_manager.borrow_mut().resize_render_targets(width, height);
+                }
                 glfw::WindowEvent::Key(Key::R, _, Action::Press, _) => {
-                    if reload_and_execute_script(&lua,"./example.lua").inspect_err(|e| println!("{}",e)).is_ok(){
+                    // A script that fails to run keeps the previous one loaded
+                    let executed = reload_and_execute_script(&lua,SCRIPT_PATH).inspect_err(|e| println!("{}",e)).is_ok();
+                    if executed {
                         lua_ok = true;
                         lua_loaded = false;
                     }
@@ -118,4 +420,5 @@ fn main() {
             }
         }
     }
-}
\ No newline at end of file
+    report_leaks(&resource_manager);
+}
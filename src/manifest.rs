@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::atlas::{self, AtlasOptions};
+use crate::hot_reload::ShaderFiles;
+use crate::mesh::{PlanarTextureVertex, Topology};
+use crate::sampler::{Filter, MipmapMode, SamplerDesc, WrapMode};
+use crate::vfs;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    #[serde(default)]
+    samplers: Map<String, Value>,
+    #[serde(default)]
+    shaders: Map<String, Value>,
+    #[serde(default)]
+    textures: Map<String, Value>,
+    #[serde(default)]
+    atlases: Map<String, Value>,
+    #[serde(default)]
+    meshes: Map<String, Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SamplerOptions {
+    filter: Option<String>,
+    min_filter: Option<String>,
+    mag_filter: Option<String>,
+    mipmaps: Option<String>,
+    wrap: Option<String>,
+    wrap_s: Option<String>,
+    wrap_t: Option<String>,
+    anisotropy: Option<f32>,
+    border_color: Option<[f32; 4]>,
+}
+
+impl SamplerOptions {
+    /// Same precedence as the Lua options, `filter` and `wrap` first, then the specific keys
+    fn apply(&self, base: SamplerDesc) -> Result<SamplerDesc, String> {
+        fn parse<T>(name: &Option<String>, key: &str, from_name: fn(&str) -> Option<T>) -> Result<Option<T>, String> {
+            match name {
+                Some(name) => from_name(name).map(Some).ok_or_else(|| format!("Invalid {} \"{}\"", key, name)),
+                None => Ok(None),
+            }
+        }
+        let mut desc = base;
+        if let Some(x) = parse(&self.filter, "filter", Filter::from_name)? {
+            desc.min_filter = x;
+            desc.mag_filter = x;
+        }
+        if let Some(x) = parse(&self.wrap, "wrap", WrapMode::from_name)? {
+            desc.wrap_s = x;
+            desc.wrap_t = x;
+        }
+        desc.min_filter = parse(&self.min_filter, "min_filter", Filter::from_name)?.unwrap_or(desc.min_filter);
+        desc.mag_filter = parse(&self.mag_filter, "mag_filter", Filter::from_name)?.unwrap_or(desc.mag_filter);
+        desc.mipmap_mode = parse(&self.mipmaps, "mipmaps", MipmapMode::from_name)?.unwrap_or(desc.mipmap_mode);
+        desc.wrap_s = parse(&self.wrap_s, "wrap_s", WrapMode::from_name)?.unwrap_or(desc.wrap_s);
+        desc.wrap_t = parse(&self.wrap_t, "wrap_t", WrapMode::from_name)?.unwrap_or(desc.wrap_t);
+        desc.anisotropy = self.anisotropy.unwrap_or(desc.anisotropy);
+        desc.border_color = self.border_color.unwrap_or(desc.border_color);
+        Ok(desc)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShaderEntry {
+    file: Option<String>,
+    vertex: Option<String>,
+    fragment: Option<String>,
+    #[serde(default)]
+    defines: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureEntry {
+    path: String,
+    #[serde(default)]
+    srgb: bool,
+    #[serde(default)]
+    sampler: SamplerOptions,
+    #[serde(default, rename = "async")]
+    load_async: bool,
+}
+
+/// A list of paths named after their file stem, or a name -> path map
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AtlasImages {
+    List(Vec<String>),
+    Map(BTreeMap<String, String>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AtlasEntry {
+    images: AtlasImages,
+    padding: Option<u32>,
+    max_size: Option<u32>,
+    #[serde(default)]
+    srgb: bool,
+    #[serde(default)]
+    sampler: SamplerOptions,
+    #[serde(default, rename = "async")]
+    load_async: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshEntry {
+    topology: Option<String>,
+    vertices: Vec<[f32; 4]>,
+    indices: Option<Vec<u32>>,
+}
+
+pub enum ManifestEntry {
+    Sampler(SamplerDesc),
+    Shader { files: ShaderFiles, defines: Vec<(String, String)> },
+    Texture { path: String, srgb: bool, sampler: SamplerDesc, load_async: bool },
+    Atlas { files: Vec<(String, String)>, options: AtlasOptions, load_async: bool },
+    Mesh { topology: Topology, vertices: Vec<PlanarTextureVertex>, indices: Option<Vec<u32>> },
+}
+
+/// Declarative resource lists. Every section maps resource keys to entries:
+/// ```json
+/// {
+///     "samplers": { "pixel": { "filter": "nearest", "wrap": "clamp" } },
+///     "shaders": {
+///         "sprite": { "file": "sprite.glsl", "defines": { "ALPHA_TEST": true } },
+///         "batch": { "vertex": "batch.vert", "fragment": "batch.frag" }
+///     },
+///     "textures": { "player": { "path": "player.png", "srgb": true, "sampler": { "filter": "nearest" }, "async": true } },
+///     "atlases": { "ui": { "images": ["button.png", "panel.png"], "padding": 2 } },
+///     "meshes": { "quad": { "topology": "triangle_strip", "vertices": [[-0.5, -0.5, 0, 0], [0.5, -0.5, 1, 0]] } }
+/// }
+/// ```
+/// Paths are relative to the manifest. Mesh vertices are x, y, tx, ty and indices start at 0.
+/// Sampler options are the ones `sampler_create` takes, `border_color` is an r, g, b, a array
+pub struct Manifest {
+    /// (section, key, entry) in load order: samplers, shaders, textures, atlases, then meshes
+    pub entries: Vec<(&'static str, String, ManifestEntry)>,
+    /// One per invalid entry, the valid ones are still loaded
+    pub errors: Vec<String>,
+}
+
+impl Manifest {
+    /// Fails only if the file can't be read or isn't a manifest at all
+    pub fn from_file(path: &str) -> Result<Manifest, String> {
+        let text = vfs::read_to_string(path)
+            .map_err(|e| format!("Failed to read manifest \"{}\": {}", path, e))?;
+        let file: ManifestFile = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid manifest \"{}\": {}", path, e))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |x: &str| directory.join(x).to_string_lossy().to_string();
+
+        let mut manifest = Manifest { entries: Vec::new(), errors: Vec::new() };
+        manifest.parse_section("samplers", file.samplers, |x: SamplerOptions| {
+            Ok(ManifestEntry::Sampler(x.apply(SamplerDesc::default())?))
+        });
+        manifest.parse_section("shaders", file.shaders, |x: ShaderEntry| {
+            let files = match (x.file, x.vertex, x.fragment) {
+                (Some(file), None, None) => ShaderFiles::Single(resolve(&file)),
+                (None, Some(vertex), Some(fragment)) => ShaderFiles::Separate { vertex: resolve(&vertex), fragment: resolve(&fragment) },
+                _ => return Err("expected either \"file\" or both \"vertex\" and \"fragment\"".to_string()),
+            };
+            Ok(ManifestEntry::Shader { files, defines: defines_from_map(x.defines)? })
+        });
+        manifest.parse_section("textures", file.textures, |x: TextureEntry| {
+            Ok(ManifestEntry::Texture {
+                path: resolve(&x.path),
+                srgb: x.srgb,
+                sampler: x.sampler.apply(SamplerDesc::default())?,
+                load_async: x.load_async,
+            })
+        });
+        manifest.parse_section("atlases", file.atlases, |x: AtlasEntry| {
+            let mut files = match x.images {
+                AtlasImages::List(paths) => paths.iter()
+                    .map(|path| atlas::region_name(path)
+                        .map(|name| (name, resolve(path)))
+                        .ok_or_else(|| format!("Invalid atlas image path \"{}\"", path)))
+                    .collect::<Result<Vec<_>, _>>()?,
+                AtlasImages::Map(images) => images.iter().map(|(name, path)| (name.clone(), resolve(path))).collect(),
+            };
+            // Sorted so the layout doesn't depend on the listing order
+            files.sort();
+            let mut options = AtlasOptions::default();
+            options.padding = x.padding.unwrap_or(options.padding);
+            options.max_size = x.max_size.unwrap_or(options.max_size);
+            options.srgb = x.srgb;
+            options.sampler = x.sampler.apply(options.sampler)?;
+            Ok(ManifestEntry::Atlas { files, options, load_async: x.load_async })
+        });
+        manifest.parse_section("meshes", file.meshes, |x: MeshEntry| {
+            let topology = match x.topology.as_deref() {
+                Some(name) => Topology::from_name(name).ok_or_else(|| format!("Unknown topology \"{}\"", name))?,
+                None => Topology::TriangleStrip,
+            };
+            let vertices = x.vertices.iter().map(|v| PlanarTextureVertex::new(v[0], v[1], v[2], v[3])).collect();
+            Ok(ManifestEntry::Mesh { topology, vertices, indices: x.indices })
+        });
+        Ok(manifest)
+    }
+
+    fn parse_section<T: DeserializeOwned>(&mut self, section: &'static str, entries: Map<String, Value>, parse: impl Fn(T) -> Result<ManifestEntry, String>) {
+        for (key, value) in entries {
+            match serde_json::from_value(value).map_err(|e| e.to_string()).and_then(&parse) {
+                Ok(entry) => self.entries.push((section, key, entry)),
+                Err(e) => self.errors.push(format!("{}.\"{}\": {}", section, key, e)),
+            }
+        }
+    }
+}
+
+/// `{"NAME": true, "VALUE": 3}` to `#define NAME` / `#define VALUE 3`
+fn defines_from_map(defines: Map<String, Value>) -> Result<Vec<(String, String)>, String> {
+    let mut result = Vec::new();
+    for (name, value) in defines {
+        match value {
+            Value::Bool(true) => result.push((name, String::new())),
+            Value::Bool(false) => {}
+            Value::Number(x) => result.push((name, x.to_string())),
+            Value::String(x) => result.push((name, x)),
+            _ => return Err(format!("Define \"{}\" must be a boolean, number or string", name)),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::TempDir;
+
+    fn parse(name: &str, json: &str) -> Result<Manifest, String> {
+        let dir = TempDir::new(name);
+        Manifest::from_file(&dir.write("manifest.json", json))
+    }
+
+    fn keys(manifest: &Manifest) -> Vec<String> {
+        manifest.entries.iter().map(|(section, key, _)| format!("{}.{}", section, key)).collect()
+    }
+
+    #[test]
+    fn invalid_entries_are_reported_and_the_rest_loaded() {
+        let manifest = parse("manifest-invalid", r#"{
+            "meshes": { "bad": { "topology": "hexagons", "vertices": [] }, "quad": { "vertices": [[0, 0, 0, 0]] } },
+            "textures": { "player": { "path": "player.png" }, "broken": { "srgb": true } },
+            "samplers": { "pixel": { "filter": "nearest" }, "blurry": { "filter": "fuzzy" } }
+        }"#).unwrap();
+        assert_eq!(keys(&manifest), ["samplers.pixel", "textures.player", "meshes.quad"]);
+        assert_eq!(manifest.errors.len(), 3);
+        assert!(manifest.errors.iter().any(|x| x.starts_with("samplers.\"blurry\"") && x.contains("Invalid filter \"fuzzy\"")));
+        assert!(manifest.errors.iter().any(|x| x.starts_with("textures.\"broken\"") && x.contains("path")));
+        assert!(manifest.errors.iter().any(|x| x.starts_with("meshes.\"bad\"") && x.contains("Unknown topology")));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let manifest = parse("manifest-unknown-field", r#"{
+            "textures": { "player": { "path": "player.png", "mipmaps": true } }
+        }"#).unwrap();
+        assert!(manifest.entries.is_empty());
+        assert!(manifest.errors[0].contains("mipmaps"), "{}", manifest.errors[0]);
+
+        // An unknown section means this isn't a manifest
+        let error = parse("manifest-unknown-section", r#"{ "sounds": {} }"#).err().unwrap();
+        assert!(error.contains("Invalid manifest"), "{}", error);
+    }
+
+    #[test]
+    fn shaders_take_a_file_or_both_stages() {
+        let manifest = parse("manifest-shaders", r#"{ "shaders": {
+            "single": { "file": "sprite.glsl", "defines": { "ALPHA_TEST": true, "OFF": false, "COUNT": 3 } },
+            "separate": { "vertex": "batch.vert", "fragment": "batch.frag" },
+            "both": { "file": "sprite.glsl", "vertex": "batch.vert", "fragment": "batch.frag" },
+            "half": { "vertex": "batch.vert" },
+            "none": {}
+        } }"#).unwrap();
+        assert_eq!(keys(&manifest), ["shaders.single", "shaders.separate"]);
+        assert_eq!(manifest.errors.len(), 3);
+        assert!(manifest.errors.iter().all(|x| x.contains("expected either \"file\"")));
+
+        for (_, key, entry) in &manifest.entries {
+            match (key.as_str(), entry) {
+                ("single", ManifestEntry::Shader { files: ShaderFiles::Single(file), defines }) => {
+                    assert!(file.ends_with("sprite.glsl"));
+                    assert_eq!(defines, &[("ALPHA_TEST".to_string(), String::new()), ("COUNT".to_string(), "3".to_string())]);
+                }
+                ("separate", ManifestEntry::Shader { files: ShaderFiles::Separate { vertex, fragment }, .. }) => {
+                    assert!(vertex.ends_with("batch.vert") && fragment.ends_with("batch.frag"));
+                }
+                _ => panic!("unexpected entry {}", key),
+            }
+        }
+    }
+
+    #[test]
+    fn atlas_images_as_list_or_map() {
+        let manifest = parse("manifest-atlases", r#"{ "atlases": {
+            "list": { "images": ["ui/panel.png", "ui/button.png"], "padding": 4 },
+            "map": { "images": { "ok": "check.png", "cancel": "cross.png" } }
+        } }"#).unwrap();
+        assert!(manifest.errors.is_empty(), "{:?}", manifest.errors);
+        let names = |key: &str| match manifest.entries.iter().find(|x| x.1 == key) {
+            Some((_, _, ManifestEntry::Atlas { files, .. })) => files.iter().map(|x| x.0.clone()).collect::<Vec<_>>(),
+            _ => panic!("missing atlas {}", key),
+        };
+        // Named after the file stem, sorted by name
+        assert_eq!(names("list"), ["button", "panel"]);
+        assert_eq!(names("map"), ["cancel", "ok"]);
+        match &manifest.entries.iter().find(|x| x.1 == "list").unwrap().2 {
+            ManifestEntry::Atlas { files, options, .. } => {
+                assert_eq!(options.padding, 4);
+                assert!(files[0].1.ends_with("button.png"));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,6 +1,186 @@
+use gl::types::GLenum;
+use rgms_derive::MeshVertex;
+
 use crate::buffers::{BufferObject, VertexArrayObject};
+/// Vertex or instance layout, usually derived. Outside the engine the derive needs its path:
+///
+/// ```
+/// use rgms::mesh::MeshVertex;
+/// use rgms_derive::MeshVertex;
+///
+/// #[repr(C)]
+/// #[derive(MeshVertex)]
+/// #[mesh_vertex(crate = rgms)]
+/// struct Vertex {
+///     position: [f32; 2],
+///     #[location(3)]
+///     #[normalized]
+///     color: [u8; 4],
+/// }
+/// assert_eq!(Vertex::LOCATIONS, 4);
+/// ```
+///
+/// Field offsets only match the GPU layout with `#[repr(C)]`:
+///
+/// ```compile_fail
+/// # use rgms_derive::MeshVertex;
+/// #[derive(MeshVertex)]
+/// #[mesh_vertex(crate = rgms)]
+/// struct Vertex {
+///     position: [f32; 2],
+/// }
+/// ```
+///
+/// A field is either normalized to floats or kept as integers:
+///
+/// ```compile_fail
+/// # use rgms_derive::MeshVertex;
+/// #[repr(C)]
+/// #[derive(MeshVertex)]
+/// #[mesh_vertex(crate = rgms)]
+/// struct Vertex {
+///     #[normalized]
+///     #[integer]
+///     color: [u8; 4],
+/// }
+/// ```
 pub trait MeshVertex {
-    unsafe fn enable_vertex_attrib();
+    /// Number of attribute locations the layout occupies
+    const LOCATIONS: u32;
+    /// Sets up the attributes of the bound buffer starting at `base_location`,
+    /// `offset` is in bytes and `divisor` is 0 for per vertex and 1 for per instance data
+    ///
+    /// # Safety
+    /// Needs a current GL context with a vertex array and an array buffer bound
+    unsafe fn enable_vertex_attrib(base_location: u32, divisor: u32, offset: usize);
+}
+
+/// Field types usable in a `#[derive(MeshVertex)]` layout
+pub trait VertexAttribute {
+    /// Components per location, 1 to 4
+    const COMPONENTS: i32;
+    const GL_TYPE: GLenum;
+    /// Matrices take one location per column
+    const LOCATIONS: u32 = 1;
+}
+
+macro_rules! vertex_attribute {
+    ($gl_type:expr, $($t:ty => $components:expr, $locations:expr;)*) => {
+        $(
+            impl VertexAttribute for $t {
+                const COMPONENTS: i32 = $components;
+                const GL_TYPE: GLenum = $gl_type;
+                const LOCATIONS: u32 = $locations;
+            }
+        )*
+    };
+}
+vertex_attribute!(gl::FLOAT,
+    f32 => 1, 1; [f32; 1] => 1, 1; [f32; 2] => 2, 1; [f32; 3] => 3, 1; [f32; 4] => 4, 1;
+    glam::Vec2 => 2, 1; glam::Vec3 => 3, 1; glam::Vec4 => 4, 1;
+    [f32; 6] => 2, 3; [f32; 9] => 3, 3; [f32; 16] => 4, 4;
+);
+vertex_attribute!(gl::UNSIGNED_BYTE, u8 => 1, 1; [u8; 2] => 2, 1; [u8; 3] => 3, 1; [u8; 4] => 4, 1;);
+vertex_attribute!(gl::BYTE, i8 => 1, 1; [i8; 2] => 2, 1; [i8; 3] => 3, 1; [i8; 4] => 4, 1;);
+vertex_attribute!(gl::UNSIGNED_SHORT, u16 => 1, 1; [u16; 2] => 2, 1; [u16; 3] => 3, 1; [u16; 4] => 4, 1;);
+vertex_attribute!(gl::SHORT, i16 => 1, 1; [i16; 2] => 2, 1; [i16; 3] => 3, 1; [i16; 4] => 4, 1;);
+vertex_attribute!(gl::UNSIGNED_INT, u32 => 1, 1; [u32; 2] => 2, 1; [u32; 3] => 3, 1; [u32; 4] => 4, 1;);
+vertex_attribute!(gl::INT, i32 => 1, 1; [i32; 2] => 2, 1; [i32; 3] => 3, 1; [i32; 4] => 4, 1;);
+
+/// Attribute setup used by `#[derive(MeshVertex)]`, `offset` is the field's byte offset in the bound buffer
+///
+/// # Safety
+/// Needs a current GL context with a vertex array and an array buffer bound
+pub unsafe fn enable_attribute<T: VertexAttribute>(location: u32, stride: i32, offset: usize, normalized: bool, integer: bool, divisor: u32) {
+    let location_size = std::mem::size_of::<T>() / T::LOCATIONS as usize;
+    for i in 0..T::LOCATIONS {
+        let pointer = (offset + i as usize * location_size) as *const _;
+        gl::EnableVertexAttribArray(location + i);
+        if integer {
+            gl::VertexAttribIPointer(location + i, T::COMPONENTS, T::GL_TYPE, stride, pointer);
+        } else {
+            gl::VertexAttribPointer(location + i, T::COMPONENTS, T::GL_TYPE, if normalized { gl::TRUE } else { gl::FALSE }, stride, pointer);
+        }
+        gl::VertexAttribDivisor(location + i, divisor);
+    }
+}
+
+pub trait MeshIndex {
+    const INDEX_TYPE: IndexType;
+}
+impl MeshIndex for u8 {
+    const INDEX_TYPE: IndexType = IndexType::U8;
+}
+impl MeshIndex for u16 {
+    const INDEX_TYPE: IndexType = IndexType::U16;
+}
+impl MeshIndex for u32 {
+    const INDEX_TYPE: IndexType = IndexType::U32;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexType {
+    U8,
+    U16,
+    U32,
+}
+impl IndexType {
+    pub fn gl_enum(self) -> GLenum {
+        match self {
+            IndexType::U8 => gl::UNSIGNED_BYTE,
+            IndexType::U16 => gl::UNSIGNED_SHORT,
+            IndexType::U32 => gl::UNSIGNED_INT,
+        }
+    }
+    pub fn size(self) -> usize {
+        match self {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Topology {
+    Points,
+    Lines,
+    LineStrip,
+    LineLoop,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+}
+impl Topology {
+    pub fn gl_enum(self) -> GLenum {
+        match self {
+            Topology::Points => gl::POINTS,
+            Topology::Lines => gl::LINES,
+            Topology::LineStrip => gl::LINE_STRIP,
+            Topology::LineLoop => gl::LINE_LOOP,
+            Topology::Triangles => gl::TRIANGLES,
+            Topology::TriangleStrip => gl::TRIANGLE_STRIP,
+            Topology::TriangleFan => gl::TRIANGLE_FAN,
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Topology> {
+        match name {
+            "points" => Some(Topology::Points),
+            "lines" => Some(Topology::Lines),
+            "line_strip" => Some(Topology::LineStrip),
+            "line_loop" => Some(Topology::LineLoop),
+            "triangles" => Some(Topology::Triangles),
+            "triangle_strip" => Some(Topology::TriangleStrip),
+            "triangle_fan" => Some(Topology::TriangleFan),
+            _ => None,
+        }
+    }
+}
+
+struct InstanceLayout {
+    enable_vertex_attrib: unsafe fn(u32, u32, usize),
+    base_location: u32,
+    stride: usize,
 }
 
 
@@ -8,9 +188,18 @@ pub struct Mesh{
     vao: VertexArrayObject,
     vbo: BufferObject,
     ebo: BufferObject,
+    instance_vbo: BufferObject,
 
+    topology: Topology,
+    index_type: Option<IndexType>,
     vertices_count: u32, 
     indices_count: u32,
+    instance_layout: Option<InstanceLayout>,
+}
+impl Default for Mesh {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Mesh {
     pub fn new() -> Mesh{
@@ -18,14 +207,19 @@ impl Mesh {
             vao: VertexArrayObject::new(),
             vbo: BufferObject::new(gl::ARRAY_BUFFER),
             ebo: BufferObject::new(gl::ELEMENT_ARRAY_BUFFER),
+            instance_vbo: BufferObject::new(gl::ARRAY_BUFFER),
+            topology: Topology::Triangles,
+            index_type: None,
             vertices_count: 0,
             indices_count: 0,
+            instance_layout: None,
         }
     }
 
-    pub fn create<Tv,Ti>(&mut self,vertices: &[Tv],indices:Option<&[Ti]>) where Tv : MeshVertex
+    pub fn create<Tv,Ti>(&mut self,topology: Topology,vertices: &[Tv],indices:Option<&[Ti]>) where Tv : MeshVertex, Ti : MeshIndex
     {
         self.delete();
+        self.topology = topology;
 
         self.vao.create();
         self.vbo.create();
@@ -37,22 +231,102 @@ impl Mesh {
 
         self.vertices_count = vertices.len() as u32;
 
-        unsafe { Tv::enable_vertex_attrib() };
+        unsafe { Tv::enable_vertex_attrib(0, 0, 0) };
 
         if let Some(idx) = indices {
             self.ebo.create();
             self.ebo.bind();
             self.ebo.set_data(idx, gl::STATIC_DRAW);
             self.indices_count = idx.len() as u32;
+            self.index_type = Some(Ti::INDEX_TYPE);
+        }
+    }
+    /// Creates the mesh with the smallest index type that fits, `indices` start at 0
+    pub fn create_planar(&mut self, topology: Topology, vertices: &[PlanarTextureVertex], indices: Option<&[u32]>) -> Result<(), String> {
+        match indices {
+            Some(indices) => {
+                if let Some(i) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+                    return Err(format!("Index {} is out of range for {} vertices", i, vertices.len()));
+                }
+                if vertices.len() <= u8::MAX as usize + 1 {
+                    let indices: Vec<u8> = indices.iter().map(|&i| i as u8).collect();
+                    self.create(topology, vertices, Some(&indices));
+                } else if vertices.len() <= u16::MAX as usize + 1 {
+                    let indices: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+                    self.create(topology, vertices, Some(&indices));
+                } else {
+                    self.create(topology, vertices, Some(indices));
+                }
+            }
+            None => self.create::<PlanarTextureVertex, u8>(topology, vertices, None),
+        }
+        unsafe { gl::BindVertexArray(0) };
+        Ok(())
+    }
+    /// Uploads per instance data read by the attributes following the vertex layout at `base_location`
+    pub fn set_instances<Ti>(&mut self, instances: &[Ti], base_location: u32, usage: GLenum) where Ti : MeshVertex
+    {
+        self.vao.bind();
+        if self.instance_layout.is_none() {
+            self.instance_vbo.create();
+        }
+        self.instance_vbo.set_data(instances, usage);
+        self.instance_layout = Some(InstanceLayout {
+            enable_vertex_attrib: Ti::enable_vertex_attrib,
+            base_location,
+            stride: std::mem::size_of::<Ti>(),
+        });
+        unsafe {
+            Ti::enable_vertex_attrib(base_location, 1, 0);
+            gl::BindVertexArray(0);
         }
     }
     pub fn bind(&self) {
         self.vao.bind();
     }
+    /// Number of elements `draw` submits, indices if the mesh has them
+    pub fn element_count(&self) -> u32 {
+        if self.index_type.is_some() { self.indices_count } else { self.vertices_count }
+    }
+    pub fn draw(&self) {
+        self.draw_range(0, self.element_count() as i32);
+    }
+    /// Draws `count` elements starting at `first`, vertices or indices depending on the mesh
+    pub fn draw_range(&self, first: i32, count: i32) {
+        self.vao.bind();
+        unsafe {
+            match self.index_type {
+                Some(index_type) => gl::DrawElements(self.topology.gl_enum(), count, index_type.gl_enum(), (first as usize * index_type.size()) as *const _),
+                None => gl::DrawArrays(self.topology.gl_enum(), first, count),
+            }
+        }
+    }
+    pub fn draw_instanced(&self, count: i32) {
+        self.draw_instanced_range(0, count);
+    }
+    /// Draws `count` instances starting at instance `first`
+    pub fn draw_instanced_range(&self, first: usize, count: i32) {
+        self.vao.bind();
+        if let Some(layout) = &self.instance_layout {
+            self.instance_vbo.bind();
+            unsafe { (layout.enable_vertex_attrib)(layout.base_location, 1, first * layout.stride) };
+        }
+        unsafe {
+            match self.index_type {
+                Some(index_type) => gl::DrawElementsInstanced(self.topology.gl_enum(), self.indices_count as i32, index_type.gl_enum(), std::ptr::null(), count),
+                None => gl::DrawArraysInstanced(self.topology.gl_enum(), 0, self.vertices_count as i32, count),
+            }
+        }
+    }
     fn delete(&mut self){
         self.vao = VertexArrayObject::new();
         self.vbo = BufferObject::new(gl::ARRAY_BUFFER);
         self.ebo = BufferObject::new(gl::ELEMENT_ARRAY_BUFFER);
+        self.instance_vbo = BufferObject::new(gl::ARRAY_BUFFER);
+        self.instance_layout = None;
+        self.index_type = None;
+        self.vertices_count = 0;
+        self.indices_count = 0;
     }
 }
 impl Drop for Mesh {
@@ -63,22 +337,14 @@ impl Drop for Mesh {
 
 
 
+#[repr(C)]
+#[derive(MeshVertex)]
 pub struct PlanarTextureVertex{
-    _x:[u8;std::mem::size_of::<f32>()*4]
-}
-impl MeshVertex for PlanarTextureVertex {
-    unsafe fn enable_vertex_attrib() {
-        gl::EnableVertexAttribArray(0);
-        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 4 * std::mem::size_of::<f32>() as i32, 0 as *const _);
-        gl::EnableVertexAttribArray(1);
-        gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, 4 * std::mem::size_of::<f32>() as i32, (2 * std::mem::size_of::<f32>()) as *const _);
-    }
+    position: [f32; 2],
+    tex_coord: [f32; 2],
 }
 impl PlanarTextureVertex {
     pub fn new(x: f32, y: f32, tx: f32, ty: f32) -> Self {
-        let bytes: [u8; std::mem::size_of::<f32>() * 4] = unsafe {
-            std::mem::transmute([x, y, tx, ty])
-        };
-        Self { _x: bytes }
+        Self { position: [x, y], tex_coord: [tx, ty] }
     }
 }
\ No newline at end of file
@@ -0,0 +1,124 @@
+use gl::types::{GLint, GLuint};
+
+use crate::buffers::{FramebufferObject, RenderbufferObject};
+use crate::sampler::SamplerDesc;
+use crate::texture::{Texture, TextureFormat};
+
+pub struct RenderTarget {
+    framebuffer: FramebufferObject,
+    color: Texture,
+    depth_stencil: Option<RenderbufferObject>,
+    width: i32,
+    height: i32,
+    follow_window: bool,
+}
+
+impl RenderTarget {
+    pub fn create_new(width: i32, height: i32, depth_stencil: bool) -> Result<RenderTarget, String> {
+        let mut x = RenderTarget {
+            framebuffer: FramebufferObject::new(),
+            color: Texture::create_new(gl::TEXTURE_2D),
+            depth_stencil: if depth_stencil { Some(RenderbufferObject::new()) } else { None },
+            width: 0,
+            height: 0,
+            follow_window: false,
+        };
+        x.framebuffer.create();
+        // No mipmaps are generated, so sampling has to stay on level 0
+        x.color.set_sampler(&SamplerDesc::clamped());
+        if let Some(depth_stencil) = &mut x.depth_stencil {
+            depth_stencil.create();
+        }
+        x.resize(width, height)?;
+        Ok(x)
+    }
+
+    /// Reallocates the attachments, contents are lost. The framebuffer bound before stays bound
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<(), String> {
+        if width <= 0 || height <= 0 {
+            return Err(format!("Invalid render target size {}x{}", width, height));
+        }
+        let binding = FramebufferBinding::current();
+        self.width = width;
+        self.height = height;
+
+        self.color.set_texture_wo_mipmap(TextureFormat::Rgba8, width, height, None);
+        self.framebuffer.attach_texture(gl::COLOR_ATTACHMENT0, &self.color);
+
+        if let Some(depth_stencil) = &self.depth_stencil {
+            depth_stencil.set_storage(gl::DEPTH24_STENCIL8, width, height);
+            self.framebuffer.attach_renderbuffer(gl::DEPTH_STENCIL_ATTACHMENT, depth_stencil);
+        }
+
+        let status = self.framebuffer.check_status();
+        binding.restore();
+        status
+    }
+
+    /// Binds the target for drawing and matches the viewport to it
+    pub fn bind(&self) {
+        self.framebuffer.bind();
+        unsafe { gl::Viewport(0, 0, self.width, self.height) };
+    }
+
+    /// Sampled by sprites and meshes drawn with the target's key
+    pub fn color_texture(&self) -> &Texture {
+        &self.color
+    }
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+    pub fn follow_window(&self) -> bool {
+        self.follow_window
+    }
+    pub fn set_follow_window(&mut self, follow_window: bool) {
+        self.follow_window = follow_window;
+    }
+}
+
+/// Framebuffer and viewport that were bound before a render target, restored when it is unbound
+pub struct FramebufferBinding {
+    framebuffer: GLint,
+    viewport: [GLint; 4],
+}
+
+impl FramebufferBinding {
+    pub fn current() -> FramebufferBinding {
+        let mut x = FramebufferBinding { framebuffer: 0, viewport: [0; 4] };
+        unsafe {
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut x.framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, x.viewport.as_mut_ptr());
+        }
+        x
+    }
+    pub fn restore(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer as GLuint);
+            gl::Viewport(self.viewport[0], self.viewport[1], self.viewport[2], self.viewport[3]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+
+    #[test]
+    fn resizing_keeps_the_bound_framebuffer() {
+        let Some(_context) = HeadlessContext::for_test() else {
+            return;
+        };
+        let bound = RenderTarget::create_new(4, 4, false).unwrap();
+        bound.bind();
+        let before = FramebufferBinding::current();
+        assert_ne!(before.framebuffer, 0);
+
+        // Creating a target resizes it too
+        let mut other = RenderTarget::create_new(2, 2, true).unwrap();
+        other.resize(8, 8).unwrap();
+        let after = FramebufferBinding::current();
+        assert_eq!((after.framebuffer, after.viewport), (before.framebuffer, before.viewport));
+        assert_eq!(other.size(), (8, 8));
+    }
+}
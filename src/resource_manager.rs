@@ -1,83 +1,667 @@
-use std::any::Any;
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use crate::asset_loader::{AssetLoader, AssetRequest, Loaded, LoadedAsset};
+use crate::atlas::{Atlas, AtlasOptions, AtlasRegion};
+use crate::blend::BlendMode;
 use crate::camera::Camera;
+use crate::handle::{Handle, Storage};
+use crate::hot_reload::{FileWatcher, ResourceSource, ShaderFiles};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::mesh::Mesh;
+use crate::render_target::{FramebufferBinding, RenderTarget};
+use crate::sampler::{Filter, MipmapMode, Sampler, SamplerDesc};
+use crate::shader::{Shader, ShaderCache, ShaderError};
+use crate::sprite_batch::SpriteBatch;
+use crate::texture::{Texture, TextureFormat, TextureImage, TexturePixels};
+
+/// Implemented by every type the resource manager stores, maps it to its storage
+pub trait Resource: Sized + 'static {
+    fn storage(resources: &Resources) -> &Storage<Self>;
+    fn storage_mut(resources: &mut Resources) -> &mut Storage<Self>;
+}
+
+macro_rules! resource_storages {
+    ($($t:ty => $field:ident),* $(,)?) => {
+        pub struct Resources {
+            $($field: Storage<$t>,)*
+        }
+        impl Resources {
+            fn new() -> Self {
+                Self { $($field: Storage::new(),)* }
+            }
+            fn remove(&mut self, key: &str) {
+                $(self.$field.remove(key);)*
+            }
+            /// A key names one resource, adding it as T drops it from the other types
+            fn remove_other_types<T: 'static>(&mut self, key: &str) -> bool {
+                let mut removed = false;
+                $(if TypeId::of::<$t>() != TypeId::of::<T>() {
+                    removed |= self.$field.remove(key).is_some();
+                })*
+                removed
+            }
+        }
+        $(impl Resource for $t {
+            fn storage(resources: &Resources) -> &Storage<Self> {
+                &resources.$field
+            }
+            fn storage_mut(resources: &mut Resources) -> &mut Storage<Self> {
+                &mut resources.$field
+            }
+        })*
+    };
+}
+
+resource_storages! {
+    Shader => shaders,
+    Texture => textures,
+    Mesh => meshes,
+    Sampler => samplers,
+    Atlas => atlases,
+    RenderTarget => render_targets,
+}
+
+impl Resources {
+    fn texture(&self, handle: TextureHandle) -> Option<&Texture> {
+        match handle {
+            TextureHandle::Texture(x) => self.textures.get(x),
+            TextureHandle::Atlas(x) => self.atlases.get(x).map(|x| x.texture()),
+            TextureHandle::RenderTarget(x) => self.render_targets.get(x).map(|x| x.color_texture()),
+        }
+    }
+}
+
+/// Anything that can be sampled as a texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TextureHandle {
+    Texture(Handle<Texture>),
+    Atlas(Handle<Atlas>),
+    /// The target's color attachment
+    RenderTarget(Handle<RenderTarget>),
+}
+
+/// What keeps a resource loaded, it is unloaded once nothing does
+#[derive(Default)]
+struct ResourceRefs {
+    groups: HashSet<String>,
+    /// `acquire` calls not matched by a `release`
+    count: u32,
+}
+
+impl ResourceRefs {
+    fn is_unused(&self) -> bool {
+        self.groups.is_empty() && self.count == 0
+    }
+}
 
 pub struct ResourceManager {
-    resources: HashMap<u64, Box<dyn Any>>,
+    resources: Resources,
+    refs: HashMap<String, ResourceRefs>,
+    /// Group new resources are added to
+    group: String,
+    /// Group references from before `begin_reload`, restored if the reload fails
+    reload_groups: Option<HashMap<String, HashSet<String>>>,
     camera: Box<Camera>,
-
+    sprite_batch: SpriteBatch,
+    shader_cache: ShaderCache,
+    capture_requests: Vec<String>,
+    sources: HashMap<String, ResourceSource>,
+    watcher: Option<FileWatcher>,
+    /// Normalized paths of the loaded manifests
+    manifests: HashSet<PathBuf>,
+    blend_mode: BlendMode,
+    loader: AssetLoader,
+    /// Set while a render target is bound
+    target_binding: Option<FramebufferBinding>,
+    revision: u64,
 }
 
 impl ResourceManager {
+    pub const DEFAULT_GROUP: &'static str = "default";
+
+    // Needs a GL context and starts the asset loader threads, not something Default should do
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
-            resources: HashMap::new(),
-            camera: Box::new(Camera::new())
+            resources: Resources::new(),
+            refs: HashMap::new(),
+            group: Self::DEFAULT_GROUP.to_string(),
+            reload_groups: None,
+            camera: Box::new(Camera::new()),
+            sprite_batch: SpriteBatch::new(),
+            shader_cache: ShaderCache::new(),
+            capture_requests: Vec::new(),
+            sources: HashMap::new(),
+            watcher: None,
+            manifests: HashSet::new(),
+            blend_mode: BlendMode::Alpha,
+            loader: AssetLoader::new(std::thread::available_parallelism().map_or(2, |x| x.get()).min(4)),
+            target_binding: None,
+            revision: 0,
         }
     }
 
-    pub fn add_resource<T: 'static>(&mut self, key: &str, resource: T) {
-        let hash = Self::hash_string(key);
-        if self.resources.contains_key(&hash) {
+    /// Adds the resource to the current group. Replacing an existing key of the same type keeps its handle
+    pub fn add_resource<T: Resource>(&mut self, key: &str, resource: T) -> Handle<T> {
+        self.add_ref(key);
+        self.insert_resource(key, resource)
+    }
+    fn insert_resource<T: Resource>(&mut self, key: &str, resource: T) -> Handle<T> {
+        self.revision += 1;
+        let replaced = self.resources.remove_other_types::<T>(key);
+        let (handle, old) = T::storage_mut(&mut self.resources).insert(key, resource);
+        if replaced || old.is_some() {
             println!("\"{}\" was already found in resource map, replacing", key);
-            self.resources.remove(&hash);
-        }
-        self.resources.insert(hash, Box::new(resource));
-    }
-
-    pub fn get_resource<T: 'static>(&self, key: &str) -> Option<&Box<T>> {
-        let hash = Self::hash_string(key);
-        if let Some(boxed_any) = self.resources.get(&hash) {
-            let type_name = std::any::type_name_of_val(&*boxed_any);
-            match boxed_any.downcast_ref::<Box<T>>() {
-                Some(value) => Some(value),
-                None => {
-                    println!("Failed to downcast: expected type {}, actual type {}", 
-                        std::any::type_name::<T>(), 
-                        type_name);
-                    None
+        }
+        handle
+    }
+
+    /// Changes whenever a key is added, replaced or unloaded, or an atlas is rebuilt.
+    /// Handles and regions looked up by key stay current as long as it doesn't
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn handle<T: Resource>(&self, key: &str) -> Option<Handle<T>> {
+        T::storage(&self.resources).handle(key)
+    }
+    /// None once the resource was unloaded
+    pub fn get<T: Resource>(&self, handle: Handle<T>) -> Option<&T> {
+        T::storage(&self.resources).get(handle)
+    }
+    pub fn get_mut<T: Resource>(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        T::storage_mut(&mut self.resources).get_mut(handle)
+    }
+
+    pub fn get_resource<T: Resource>(&self, key: &str) -> Option<&T> {
+        self.get(self.handle(key)?)
+    }
+
+    pub fn get_resource_mut<T: Resource>(&mut self, key: &str) -> Option<&mut T> {
+        self.get_mut(self.handle(key)?)
+    }
+
+    /// Resizes every render target that follows the window size
+    pub fn resize_render_targets(&mut self, width: i32, height: i32) {
+        for target in self.resources.render_targets.values_mut() {
+            if target.follow_window() {
+                if let Err(e) = target.resize(width, height) {
+                    println!("Failed to resize render target: {}", e);
                 }
             }
-        } else {
-            None
         }
     }
 
-    pub fn get_resource_mut<T: 'static>(&mut self, key: &str) -> Option<&mut Box<T>> {
-        let hash = Self::hash_string(key);
-        if let Some(boxed_any) = self.resources.get_mut(&hash) {
-            let type_name = std::any::type_name_of_val(&*boxed_any);
-            match boxed_any.downcast_mut::<Box<T>>() {
-                Some(value) => Some(value),
-                None => {
-                    println!("Failed to downcast: expected type {}, actual type {}", 
-                        std::any::type_name::<T>(), 
-                        type_name);
-                    None
+    /// Sends the queued sprites to the current framebuffer, then draws go into the target until `unbind_render_target`
+    pub fn bind_render_target(&mut self, key: &str) -> Result<(), String> {
+        let handle = self.handle::<RenderTarget>(key).ok_or_else(|| format!("Render target \"{}\" not found", key))?;
+        self.flush_sprites();
+        if self.target_binding.is_none() {
+            self.target_binding = Some(FramebufferBinding::current());
+        }
+        self.resources.render_targets.get(handle).unwrap().bind();
+        Ok(())
+    }
+    /// Returns to the framebuffer bound before the first `bind_render_target`, false if no target was bound
+    pub fn unbind_render_target(&mut self) -> bool {
+        let Some(binding) = self.target_binding.take() else {
+            return false;
+        };
+        self.flush_sprites();
+        binding.restore();
+        true
+    }
+
+    /// Texture stored under `key`, a plain texture, an atlas or a render target's color
+    pub fn texture_handle(&self, key: &str) -> Option<TextureHandle> {
+        self.resources.textures.handle(key).map(TextureHandle::Texture)
+            .or_else(|| self.resources.atlases.handle(key).map(TextureHandle::Atlas))
+            .or_else(|| self.resources.render_targets.handle(key).map(TextureHandle::RenderTarget))
+    }
+    pub fn texture(&self, handle: TextureHandle) -> Option<&Texture> {
+        self.resources.texture(handle)
+    }
+    /// Looks up an `"atlas/region"` key
+    pub fn atlas_region(&self, key: &str) -> Option<(Handle<Atlas>, AtlasRegion)> {
+        let (atlas, region) = key.rsplit_once('/')?;
+        let handle = self.resources.atlases.handle(atlas)?;
+        Some((handle, *self.resources.atlases.get(handle)?.region(region)?))
+    }
+    /// Texture to draw `key` with, atlas regions resolve to their atlas
+    pub fn resolve_sprite(&self, key: &str) -> Option<(TextureHandle, Option<AtlasRegion>)> {
+        match self.atlas_region(key) {
+            Some((atlas, region)) => Some((TextureHandle::Atlas(atlas), Some(region))),
+            None => Some((self.texture_handle(key)?, None)),
+        }
+    }
+
+    pub fn set_group(&mut self, group: &str) {
+        self.group = group.to_string();
+    }
+    fn add_ref(&mut self, key: &str) {
+        self.refs.entry(key.to_string()).or_default().groups.insert(self.group.clone());
+    }
+    /// Keeps `key` loaded regardless of its groups until a matching `release`
+    pub fn acquire(&mut self, key: &str) -> bool {
+        match self.refs.get_mut(key) {
+            Some(refs) => {
+                refs.count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn release(&mut self, key: &str) {
+        match self.refs.get_mut(key) {
+            Some(refs) if refs.count > 0 => refs.count -= 1,
+            _ => println!("\"{}\" was released more often than acquired", key),
+        }
+        self.unload_unused();
+    }
+    /// Drops the group's reference to its resources, unloading the ones nothing else keeps
+    pub fn unload_group(&mut self, group: &str) {
+        for refs in self.refs.values_mut() {
+            refs.groups.remove(group);
+        }
+        self.unload_unused();
+    }
+    fn unload_unused(&mut self) {
+        // Resources are only dropped once a reload has decided what it keeps
+        if self.reload_groups.is_some() {
+            return;
+        }
+        let unused: Vec<String> = self.refs.iter()
+            .filter(|(_, refs)| refs.is_unused())
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !unused.is_empty() {
+            self.revision += 1;
+        }
+        for key in unused {
+            self.refs.remove(&key);
+            self.resources.remove(&key);
+            self.sources.remove(&key);
+        }
+    }
+    /// Keys still acquired and never released, with their outstanding count
+    pub fn leaked_resources(&self) -> Vec<(String, u32)> {
+        let mut leaked: Vec<(String, u32)> = self.refs.iter()
+            .filter(|(_, refs)| refs.count > 0)
+            .map(|(key, refs)| (key.clone(), refs.count))
+            .collect();
+        leaked.sort();
+        leaked
+    }
+
+    /// Starts reloading the script, every group reference is dropped and the resources loaded again
+    /// get theirs back. `end_reload` unloads the ones that didn't
+    pub fn begin_reload(&mut self) {
+        let groups = self.refs.iter_mut()
+            .map(|(key, refs)| (key.clone(), std::mem::take(&mut refs.groups)))
+            .collect();
+        self.reload_groups = Some(groups);
+        self.group = Self::DEFAULT_GROUP.to_string();
+    }
+    /// A failed reload keeps everything the previous load had
+    pub fn end_reload(&mut self, success: bool) {
+        if let Some(groups) = self.reload_groups.take() {
+            if !success {
+                for (key, groups) in groups {
+                    if let Some(refs) = self.refs.get_mut(&key) {
+                        refs.groups.extend(groups);
+                    }
+                }
+            }
+        }
+        self.group = Self::DEFAULT_GROUP.to_string();
+        self.unload_unused();
+    }
+    /// True if `key` is loaded from `source` already, adding it to the current group instead of loading it again
+    pub fn reuse(&mut self, key: &str, source: &ResourceSource) -> bool {
+        if self.sources.get(key) != Some(source) || !self.refs.contains_key(key) {
+            return false;
+        }
+        self.add_ref(key);
+        true
+    }
+
+    /// Loads a shader through the variant cache and records its files for hot reload
+    pub fn load_shader(&mut self, key: &str, files: ShaderFiles, defines: Vec<(String, String)>) -> Result<(), String> {
+        let error = |e: ShaderError| format!("Shader \"{}\": {}", key, e);
+        let stages = files.load(&defines).map_err(error)?;
+        let refs: Vec<_> = stages.iter().map(|(stage, source)| (*stage, source)).collect();
+        let s = self.shader_cache.get_or_create(&refs).map_err(error)?;
+        s.bind_ubo("Matrices", Camera::MATRICES_BINDING_POINT);
+        self.add_resource(key, s);
+        self.set_source(key, Some(ResourceSource::shader(files, defines, &stages)));
+        Ok(())
+    }
+    pub fn load_texture(&mut self, key: &str, path: &str, srgb: bool, sampler: SamplerDesc) -> Result<(), String> {
+        let source = ResourceSource::Texture { path: path.to_string(), srgb, sampler };
+        if self.reuse(key, &source) {
+            return Ok(());
+        }
+        let image = crate::texture::open_image(path, srgb)?;
+        let mut t = Texture::create_new(gl::TEXTURE_2D);
+        t.set_sampler(&sampler);
+        t.set_image(&image);
+        self.add_resource(key, t);
+        self.set_source(key, Some(source));
+        Ok(())
+    }
+    /// `files` are (region name, path) pairs
+    pub fn load_atlas(&mut self, key: &str, files: Vec<(String, String)>, options: AtlasOptions) -> Result<(), String> {
+        if self.reuse(key, &ResourceSource::Atlas { files: files.clone(), options: options.clone() }) {
+            return Ok(());
+        }
+        let atlas = Atlas::from_files(&files, &options).map_err(|e| format!("Atlas \"{}\": {}", key, e))?;
+        self.add_resource(key, atlas);
+        self.set_source(key, Some(ResourceSource::Atlas { files, options }));
+        Ok(())
+    }
+
+    /// Loads every valid entry of a manifest into the current group, returning one error per entry that failed
+    pub fn load_manifest(&mut self, path: &str) -> Vec<String> {
+        let manifest = match Manifest::from_file(path) {
+            Ok(x) => x,
+            Err(e) => return vec![e],
+        };
+        if let Some(normalized) = FileWatcher::normalize(Path::new(path)) {
+            self.watch_file(&normalized);
+            self.manifests.insert(normalized);
+        }
+        let mut errors: Vec<String> = manifest.errors.iter().map(|e| format!("{}: {}", path, e)).collect();
+        for (section, key, entry) in manifest.entries {
+            let result = match entry {
+                ManifestEntry::Sampler(desc) => {
+                    self.add_resource(&key, Sampler::create_new(&desc));
+                    Ok(())
+                }
+                ManifestEntry::Shader { files, defines } => self.load_shader(&key, files, defines),
+                ManifestEntry::Texture { path, srgb, sampler, load_async: true } => {
+                    self.load_async(&key, AssetRequest::Texture { path, srgb, sampler });
+                    Ok(())
                 }
+                ManifestEntry::Texture { path, srgb, sampler, .. } => self.load_texture(&key, &path, srgb, sampler),
+                ManifestEntry::Atlas { files, options, load_async: true } => {
+                    self.load_async(&key, AssetRequest::Atlas { files, options });
+                    Ok(())
+                }
+                ManifestEntry::Atlas { files, options, .. } => self.load_atlas(&key, files, options),
+                ManifestEntry::Mesh { topology, vertices, indices } => {
+                    let mut mesh = Mesh::new();
+                    mesh.create_planar(topology, &vertices, indices.as_deref())
+                        .map(|_| { self.add_resource(&key, mesh); })
+                }
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}.\"{}\": {}", path, section, key, e));
             }
-        } else {
-            None
         }
+        errors
+    }
+    /// True if `path` is a loaded manifest, changing one reloads the script that loaded it
+    pub fn is_manifest(&self, path: &Path) -> bool {
+        self.manifests.contains(path)
     }
 
-    pub fn clear(&mut self) {
-        self.resources.clear();
+    /// Decodes the asset on a worker, `process_loaded` uploads it under `key`.
+    /// Textures get a checkerboard placeholder until then
+    pub fn load_async(&mut self, key: &str, request: AssetRequest) {
+        let source = match &request {
+            AssetRequest::Texture { path, srgb, sampler } => Some(ResourceSource::Texture { path: path.clone(), srgb: *srgb, sampler: *sampler }),
+            AssetRequest::Atlas { files, options } => Some(ResourceSource::Atlas { files: files.clone(), options: options.clone() }),
+            // Shaders are cheap to load again, the cache skips compiling unchanged ones
+            AssetRequest::Shader { .. } => None,
+        };
+        if source.is_some_and(|x| self.reuse(key, &x)) {
+            return;
+        }
+        if let AssetRequest::Texture { .. } = request {
+            let mut placeholder = Texture::create_new(gl::TEXTURE_2D);
+            placeholder.set_sampler(&SamplerDesc {
+                min_filter: Filter::Nearest,
+                mag_filter: Filter::Nearest,
+                mipmap_mode: MipmapMode::None,
+                ..Default::default()
+            });
+            let (a, b) = ([255, 0, 255, 255], [0, 0, 0, 255]);
+            placeholder.set_image(&TextureImage {
+                format: TextureFormat::Rgba8,
+                width: 2,
+                height: 2,
+                pixels: TexturePixels::U8([a, b, b, a].concat()),
+            });
+            self.add_resource(key, placeholder);
+        }
+        self.add_ref(key);
+        self.loader.load(key, request);
+    }
+    /// Uploads decoded assets until `budget` is spent, so big loads are spread over several frames
+    pub fn process_loaded(&mut self, budget: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < budget {
+            match self.loader.next(false) {
+                Some(loaded) => self.upload_loaded(loaded),
+                None => break,
+            }
+        }
+        self.shader_cache.prune();
+    }
+    /// Blocks until every queued asset is uploaded
+    pub fn finish_loading(&mut self) {
+        while let Some(loaded) = self.loader.next(true) {
+            self.upload_loaded(loaded);
+        }
+        self.shader_cache.prune();
+    }
+    /// (uploaded, queued) assets of the current batch, equal once everything is loaded
+    pub fn loading_progress(&self) -> (usize, usize) {
+        self.loader.progress()
+    }
+    fn upload_loaded(&mut self, loaded: Loaded) {
+        let key = loaded.key;
+        // Unloaded while it was decoding
+        if !self.refs.contains_key(&key) {
+            return;
+        }
+        let asset = match loaded.result {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Failed to load \"{}\": {}", key, e);
+                return;
+            }
+        };
+        match asset {
+            LoadedAsset::Texture { image, path, srgb, sampler } => {
+                // Uploaded into the placeholder so lookups made meanwhile stay valid
+                match self.get_resource_mut::<Texture>(&key) {
+                    Some(x) => {
+                        x.set_sampler(&sampler);
+                        x.set_image(&image);
+                    }
+                    None => {
+                        let mut x = Texture::create_new(gl::TEXTURE_2D);
+                        x.set_sampler(&sampler);
+                        x.set_image(&image);
+                        self.insert_resource(&key, x);
+                    }
+                }
+                self.set_source(&key, Some(ResourceSource::Texture { path, srgb, sampler }));
+            }
+            LoadedAsset::Shader { stages, files, defines } => {
+                let refs: Vec<_> = stages.iter().map(|(stage, source)| (*stage, source)).collect();
+                match self.shader_cache.get_or_create(&refs) {
+                    Ok(shader) => {
+                        shader.bind_ubo("Matrices", Camera::MATRICES_BINDING_POINT);
+                        self.insert_resource(&key, shader);
+                        self.set_source(&key, Some(ResourceSource::shader(files, defines, &stages)));
+                    }
+                    Err(e) => println!("Shader \"{}\": {}", key, e),
+                }
+            }
+            LoadedAsset::Atlas { image, files, options } => {
+                self.insert_resource(&key, Atlas::upload(image, &options.sampler));
+                self.set_source(&key, Some(ResourceSource::Atlas { files, options }));
+            }
+        }
     }
 
-    // Private helper
-    fn hash_string(s: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        s.hash(&mut hasher);
-        hasher.finish()
+    pub fn enable_hot_reload(&mut self) {
+        match FileWatcher::new() {
+            Ok(mut watcher) => {
+                for source in self.sources.values() {
+                    for file in source.files() {
+                        watcher.watch(&file);
+                    }
+                }
+                self.watcher = Some(watcher);
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    /// Records the files `key` was built from, a resource without files (e.g. inline shader code) passes None
+    pub fn set_source(&mut self, key: &str, source: Option<ResourceSource>) {
+        match source {
+            Some(source) => {
+                if let Some(watcher) = &mut self.watcher {
+                    for file in source.files() {
+                        watcher.watch(&file);
+                    }
+                }
+                self.sources.insert(key.to_string(), source);
+            }
+            None => { self.sources.remove(key); }
+        }
+    }
+    pub fn watch_file(&mut self, path: &Path) {
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(path);
+        }
+    }
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        match &mut self.watcher {
+            Some(watcher) => watcher.changed_files(),
+            None => Vec::new(),
+        }
+    }
+    /// Rebuilds every resource that was built from `path`, keeping it under the same key.
+    /// A resource that fails to rebuild keeps its previous version
+    pub fn reload_file(&mut self, path: &Path) {
+        let keys: Vec<String> = self.sources.iter()
+            .filter(|(_, source)| source.files().iter().any(|x| FileWatcher::normalize(x).as_deref() == Some(path)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            let source = self.sources[&key].clone();
+            match source {
+                ResourceSource::Shader { files, defines, .. } => {
+                    let result = files.load(&defines).and_then(|stages| {
+                        let refs: Vec<_> = stages.iter().map(|(stage, source)| (*stage, source)).collect();
+                        Ok((self.shader_cache.get_or_create(&refs)?, stages))
+                    });
+                    match result {
+                        Ok((shader, stages)) => {
+                            shader.bind_ubo("Matrices", Camera::MATRICES_BINDING_POINT);
+                            if let Some(x) = self.get_resource_mut::<Shader>(&key) {
+                                *x = shader;
+                            }
+                            // Includes may have changed
+                            self.set_source(&key, Some(ResourceSource::shader(files, defines, &stages)));
+                            println!("Reloaded shader \"{}\"", key);
+                        }
+                        Err(e) => println!("Shader \"{}\": {}", key, e),
+                    }
+                }
+                ResourceSource::Atlas { files, options } => {
+                    match Atlas::from_files(&files, &options) {
+                        Ok(atlas) => {
+                            if let Some(x) = self.get_resource_mut::<Atlas>(&key) {
+                                *x = atlas;
+                            }
+                            // The regions moved
+                            self.revision += 1;
+                            println!("Reloaded atlas \"{}\"", key);
+                        }
+                        Err(e) => println!("Atlas \"{}\": {}", key, e),
+                    }
+                }
+                ResourceSource::Texture { path, srgb, .. } => {
+                    match crate::texture::open_image(&path, srgb) {
+                        Ok(image) => {
+                            if let Some(x) = self.get_resource_mut::<Texture>(&key) {
+                                x.set_image(&image);
+                            }
+                            println!("Reloaded texture \"{}\"", key);
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+        }
+        self.shader_cache.prune();
     }
+
     pub fn camera(&self) -> &Camera {
         &self.camera
     }
     pub fn camera_mut(&mut self) -> &mut Camera {
         &mut self.camera
     }
+    pub fn sprite_batch(&self) -> &SpriteBatch {
+        &self.sprite_batch
+    }
+    pub fn sprite_batch_mut(&mut self) -> &mut SpriteBatch {
+        &mut self.sprite_batch
+    }
+    /// Outlives the shaders so reloading a script reuses unchanged shader variants
+    pub fn shader_cache_mut(&mut self) -> &mut ShaderCache {
+        &mut self.shader_cache
+    }
+    pub fn flush_sprites(&mut self) {
+        let resources = &self.resources;
+        self.sprite_batch.flush(|state| {
+            let shader = resources.shaders.get(state.shader);
+            let texture = resources.texture(state.texture);
+            let sampler = match state.sampler {
+                Some(handle) => match resources.samplers.get(handle) {
+                    Some(x) => Some(x),
+                    None => return false,
+                },
+                None => None,
+            };
+            match (shader, texture) {
+                (Some(shader), Some(texture)) => {
+                    shader.bind();
+                    texture.bind(gl::TEXTURE0);
+                    match sampler {
+                        Some(sampler) => sampler.bind(0),
+                        None => Sampler::unbind(0),
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+        Sampler::unbind(0);
+        self.blend_mode.apply();
+    }
+    /// Blend state for everything drawn outside the sprite batch
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+        blend_mode.apply();
+    }
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+    /// Captures are taken once the current frame has been rendered
+    pub fn request_capture(&mut self, path: &str) {
+        self.capture_requests.push(path.to_string());
+    }
+    pub fn take_capture_requests(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.capture_requests)
+    }
 }
\ No newline at end of file
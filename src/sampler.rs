@@ -0,0 +1,202 @@
+use gl::types::{GLenum, GLfloat, GLint};
+
+// GL_EXT_texture_filter_anisotropic, core since 4.6 so not in the generated bindings
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipmapMode {
+    /// No mipmaps are generated, sampling stays on level 0
+    None,
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "linear" => Some(Filter::Linear),
+            _ => None,
+        }
+    }
+}
+
+impl MipmapMode {
+    pub fn from_name(name: &str) -> Option<MipmapMode> {
+        match name {
+            "none" => Some(MipmapMode::None),
+            "nearest" => Some(MipmapMode::Nearest),
+            "linear" => Some(MipmapMode::Linear),
+            _ => None,
+        }
+    }
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "mirror" => Some(WrapMode::MirroredRepeat),
+            "clamp" => Some(WrapMode::ClampToEdge),
+            "border" => Some(WrapMode::ClampToBorder),
+            _ => None,
+        }
+    }
+    pub fn gl_enum(self) -> GLenum {
+        match self {
+            WrapMode::Repeat => gl::REPEAT,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+            WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            WrapMode::ClampToBorder => gl::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    pub mipmap_mode: MipmapMode,
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    /// 1.0 disables anisotropic filtering, clamped to what the driver supports
+    pub anisotropy: f32,
+    /// Used by `WrapMode::ClampToBorder`
+    pub border_color: [f32; 4],
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmap_mode: MipmapMode::Linear,
+            wrap_s: WrapMode::Repeat,
+            wrap_t: WrapMode::Repeat,
+            anisotropy: 1.0,
+            border_color: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Linear filtering without mipmaps clamped to the edge, for render targets and atlases
+    pub fn clamped() -> Self {
+        Self {
+            mipmap_mode: MipmapMode::None,
+            wrap_s: WrapMode::ClampToEdge,
+            wrap_t: WrapMode::ClampToEdge,
+            ..Default::default()
+        }
+    }
+
+    pub fn min_filter_gl(&self) -> GLenum {
+        match (self.min_filter, self.mipmap_mode) {
+            (Filter::Nearest, MipmapMode::None) => gl::NEAREST,
+            (Filter::Linear, MipmapMode::None) => gl::LINEAR,
+            (Filter::Nearest, MipmapMode::Nearest) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, MipmapMode::Nearest) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Nearest, MipmapMode::Linear) => gl::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, MipmapMode::Linear) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+    pub fn mag_filter_gl(&self) -> GLenum {
+        match self.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        }
+    }
+    /// Anisotropy clamped to the driver's maximum, None if unsupported or disabled
+    fn anisotropy(&self) -> Option<f32> {
+        if self.anisotropy <= 1.0 {
+            return None;
+        }
+        let mut max: GLfloat = 0.0;
+        unsafe { gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max) };
+        if max < 1.0 {
+            return None;
+        }
+        Some(self.anisotropy.min(max))
+    }
+
+    /// Sets the parameters on the texture bound to `target`
+    pub fn apply_to_texture(&self, target: GLenum) {
+        unsafe {
+            gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, self.min_filter_gl() as GLint);
+            gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, self.mag_filter_gl() as GLint);
+            gl::TexParameteri(target, gl::TEXTURE_WRAP_S, self.wrap_s.gl_enum() as GLint);
+            gl::TexParameteri(target, gl::TEXTURE_WRAP_T, self.wrap_t.gl_enum() as GLint);
+            gl::TexParameterfv(target, gl::TEXTURE_BORDER_COLOR, self.border_color.as_ptr());
+            if let Some(anisotropy) = self.anisotropy() {
+                gl::TexParameterf(target, TEXTURE_MAX_ANISOTROPY, anisotropy);
+            }
+        }
+    }
+}
+
+/// Sampler object, overrides the sampling parameters of whatever texture is bound to the same unit.
+/// Textures sampled through it still need mipmaps if the desc uses them
+pub struct Sampler {
+    handle: u32,
+}
+
+impl Sampler {
+    pub fn create_new(desc: &SamplerDesc) -> Sampler {
+        let mut x = Sampler {
+            handle: 0,
+        };
+        x.create();
+        x.set_desc(desc);
+        x
+    }
+    pub fn create(&mut self) {
+        self.delete();
+        unsafe { gl::GenSamplers(1, &mut self.handle) };
+    }
+    pub fn set_desc(&mut self, desc: &SamplerDesc) {
+        unsafe {
+            gl::SamplerParameteri(self.handle, gl::TEXTURE_MIN_FILTER, desc.min_filter_gl() as GLint);
+            gl::SamplerParameteri(self.handle, gl::TEXTURE_MAG_FILTER, desc.mag_filter_gl() as GLint);
+            gl::SamplerParameteri(self.handle, gl::TEXTURE_WRAP_S, desc.wrap_s.gl_enum() as GLint);
+            gl::SamplerParameteri(self.handle, gl::TEXTURE_WRAP_T, desc.wrap_t.gl_enum() as GLint);
+            gl::SamplerParameterfv(self.handle, gl::TEXTURE_BORDER_COLOR, desc.border_color.as_ptr());
+            if let Some(anisotropy) = desc.anisotropy() {
+                gl::SamplerParameterf(self.handle, TEXTURE_MAX_ANISOTROPY, anisotropy);
+            }
+        }
+    }
+    /// `unit` is the texture unit index, not `gl::TEXTUREn`
+    pub fn bind(&self, unit: u32) {
+        unsafe { gl::BindSampler(unit, self.handle) };
+    }
+    pub fn unbind(unit: u32) {
+        unsafe { gl::BindSampler(unit, 0) };
+    }
+    pub fn delete(&mut self) {
+        if self.handle != 0 {
+            unsafe { gl::DeleteSamplers(1, &self.handle) };
+            self.handle = 0;
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
@@ -1,96 +1,458 @@
-use std::{collections::HashMap, ffi::CString, ptr};
+use std::{collections::hash_map::{DefaultHasher, Entry}, collections::HashMap, ffi::CString, hash::{Hash, Hasher}, ptr, rc::{Rc, Weak}};
 
-use gl::types::{GLchar, GLenum, GLint, GLuint};
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
 
+use crate::uniform::{self, UniformValue};
+use crate::vfs;
 
-pub struct Shader{
-    program: u32,
-    uniform_location_cache: HashMap<String, i32>,
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    pub location: i32,
+    pub gl_type: GLenum,
+    /// Array length, 1 for non arrays
+    pub size: i32,
 }
 
-impl Shader {
-    /*pub fn new() -> Shader {
-        Shader{program:0, uniform_location_cache: HashMap::new() }
-    }*/
-    pub fn create_new(vertex_shader: &str, fragment_shader:  &str) -> Shader {
-        let mut x =Shader {
-            program : 0,
-            uniform_location_cache: HashMap::new(),
-        };
-        x.create(vertex_shader,fragment_shader);
-        x
+#[derive(Clone, Debug)]
+pub struct UniformBlockInfo {
+    pub index: GLuint,
+    pub data_size: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Geometry,
+    Fragment,
+}
+impl ShaderStage {
+    pub fn gl_enum(self) -> GLenum {
+        match self {
+            ShaderStage::Vertex => gl::VERTEX_SHADER,
+            ShaderStage::Geometry => gl::GEOMETRY_SHADER,
+            ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vertex",
+            ShaderStage::Geometry => "geometry",
+            ShaderStage::Fragment => "fragment",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<ShaderStage> {
+        match name {
+            "vertex" => Some(ShaderStage::Vertex),
+            "geometry" => Some(ShaderStage::Geometry),
+            "fragment" => Some(ShaderStage::Fragment),
+            _ => None,
+        }
     }
-    pub fn create(&mut self,vertex_shader: &str, fragment_shader:  &str) {
-        self.delete();
-        let vert = Self::compile_shader(vertex_shader, gl::VERTEX_SHADER).expect("VERTEX SHADER failed to compile");
-        let frag = Self::compile_shader(fragment_shader, gl::FRAGMENT_SHADER).expect("FRAGMENT SHADER failed to compile");
-
-        unsafe { 
-            self.program = gl::CreateProgram();
-            gl::AttachShader(self.program,vert);
-            gl::AttachShader(self.program,frag);
-            let link_res = Self::link_program(self.program);
-            gl::DeleteShader(vert);
-            gl::DeleteShader(frag);
-            if link_res.is_err(){
-                self.delete();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderErrorKind {
+    Preprocess,
+    Compile(ShaderStage),
+    Link,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderErrorLine {
+    /// File the line belongs to, resolved through the `#line` directives of the preprocessor
+    pub file: Option<String>,
+    pub line: u32,
+    pub message: String,
+    /// The offending line of the source, when it could be found
+    pub source: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderError {
+    pub kind: ShaderErrorKind,
+    pub log: String,
+    pub lines: Vec<ShaderErrorLine>,
+}
+
+impl ShaderError {
+    fn new(kind: ShaderErrorKind, log: String, source: Option<&ShaderSource>) -> Self {
+        let lines = log.lines()
+            .filter_map(parse_log_line)
+            .map(|(file, line, message)| {
+                let file = source.and_then(|s| s.files.get(file as usize));
+                ShaderErrorLine {
+                    file: file.map(|f| f.path.clone()),
+                    line,
+                    message,
+                    source: file
+                        .and_then(|f| f.source.lines().nth((line as usize).saturating_sub(1)))
+                        .map(|s| s.trim().to_string()),
+                }
+            })
+            .collect();
+        ShaderError { kind, log, lines }
+    }
+    fn preprocess(message: String) -> Self {
+        ShaderError { kind: ShaderErrorKind::Preprocess, log: message, lines: Vec::new() }
+    }
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ShaderErrorKind::Preprocess => writeln!(f, "shader failed to preprocess:")?,
+            ShaderErrorKind::Compile(stage) => writeln!(f, "{} shader failed to compile:", stage.name())?,
+            ShaderErrorKind::Link => writeln!(f, "shader program failed to link:")?,
+        }
+        if self.lines.is_empty() {
+            return write!(f, "{}", self.log.trim_end());
+        }
+        for line in &self.lines {
+            match &line.file {
+                Some(file) => writeln!(f, "  {}:{}: {}", file, line.line, line.message)?,
+                None => writeln!(f, "  line {}: {}", line.line, line.message)?,
             }
-            
-        };
+            if let Some(source) = &line.source {
+                writeln!(f, "    | {}", source)?;
+            }
+        }
+        Ok(())
     }
-    pub fn bind(&self) {
-        unsafe { gl::UseProgram(self.program); };
+}
+
+pub struct ShaderSourceFile {
+    pub path: String,
+    pub source: String,
+}
+
+/// Preprocessed GLSL, `files[n]` is the file `#line _ n` refers to
+pub struct ShaderSource {
+    pub code: String,
+    pub files: Vec<ShaderSourceFile>,
+}
+
+impl ShaderSource {
+    /// Source used as is, without preprocessing
+    pub fn from_code(code: &str) -> ShaderSource {
+        ShaderSource {
+            code: code.to_string(),
+            files: vec![ShaderSourceFile { path: "<source>".to_string(), source: code.to_string() }],
+        }
+    }
+
+    pub fn from_file(path: &str, defines: &[(String, String)]) -> Result<ShaderSource, ShaderError> {
+        let source = vfs::read_to_string(path)
+            .map_err(|e| ShaderError::preprocess(format!("Failed to read \"{}\": {}", path, e)))?;
+        Self::preprocess(&source, path, defines)
     }
 
+    /// Splits a single file shader into its `#pragma stage <vertex|geometry|fragment>` sections.
+    /// Lines before the first section are shared by every stage, vertex and fragment are required
+    pub fn stages_from_file(path: &str, defines: &[(String, String)]) -> Result<Vec<(ShaderStage, ShaderSource)>, ShaderError> {
+        let source = vfs::read_to_string(path)
+            .map_err(|e| ShaderError::preprocess(format!("Failed to read \"{}\": {}", path, e)))?;
+
+        let mut sections: Vec<(ShaderStage, usize)> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let Some(stage) = line.trim().strip_prefix("#pragma stage") else {
+                continue;
+            };
+            let stage = ShaderStage::from_name(stage.trim())
+                .ok_or_else(|| ShaderError::preprocess(format!("{}:{}: unknown stage \"{}\", expected vertex, geometry or fragment", path, i + 1, stage.trim())))?;
+            if sections.iter().any(|(x, _)| *x == stage) {
+                return Err(ShaderError::preprocess(format!("{}:{}: duplicate {} section", path, i + 1, stage.name())));
+            }
+            sections.push((stage, i));
+        }
+        for required in [ShaderStage::Vertex, ShaderStage::Fragment] {
+            if !sections.iter().any(|(x, _)| *x == required) {
+                return Err(ShaderError::preprocess(format!("{}: missing \"#pragma stage {}\" section", path, required.name())));
+            }
+        }
 
-    fn delete(&mut self){
-        if self.program !=0{
-            unsafe {gl::DeleteProgram(self.program)};
-            self.program = 0;
+        let line_count = source.lines().count();
+        let prelude_end = sections.iter().map(|(_, start)| *start).min().unwrap_or(line_count);
+        let mut stages = Vec::new();
+        for (stage, start) in &sections {
+            let end = sections.iter().map(|(_, x)| *x).filter(|x| x > start).min().unwrap_or(line_count);
+            // Other sections are blanked rather than removed so line numbers stay those of the file
+            let code: String = source.lines().enumerate()
+                .map(|(i, line)| if i < prelude_end || (i > *start && i < end) { line } else { "" })
+                .collect::<Vec<_>>()
+                .join("\n");
+            stages.push((*stage, Self::preprocess(&code, path, defines)?));
         }
+        stages.sort_by_key(|(stage, _)| *stage as u32);
+        Ok(stages)
     }
 
-    pub fn set_uniform_mat3x2(&mut self, name: &str, value: &[f32; 6]) {
-        self.bind();
-        unsafe {
-            gl::UniformMatrix3x2fv(
-                self.get_uniform_location(name),
-                1,
-                gl::FALSE,
-                value.as_ptr(),
-            );
+    /// Resolves `#include "file"` relative to the including file (each file is included once)
+    /// and injects `defines` right after `#version`, an empty value defines a flag
+    pub fn preprocess(source: &str, path: &str, defines: &[(String, String)]) -> Result<ShaderSource, ShaderError> {
+        let mut result = ShaderSource { code: String::new(), files: Vec::new() };
+        let mut stack = Vec::new();
+        result.include(source, path, &mut stack)?;
+
+        // Defines go after #version, which has to stay the first directive
+        let mut defines = defines.to_vec();
+        defines.sort();
+        let mut injected = String::new();
+        for (name, value) in &defines {
+            injected.push_str(&format!("#define {} {}\n", name, value));
+        }
+        let mut version_end = 0;
+        let mut next_line = 1;
+        for (i, line) in result.code.split_inclusive('\n').enumerate() {
+            version_end += line.len();
+            if line.trim_start().starts_with("#version") {
+                next_line = i + 2;
+                break;
+            }
+        }
+        if next_line == 1 {
+            version_end = 0;
         }
+        let (version, rest) = result.code.split_at(version_end);
+        result.code = format!("{}{}#line {} 0\n{}", version, injected, next_line, rest);
+        Ok(result)
     }
 
-    fn get_uniform_location(&mut self, name: &str) -> i32 {
-        if let Some(&location) = self.uniform_location_cache.get(name) {
-            return location;
+    fn include(&mut self, source: &str, path: &str, stack: &mut Vec<String>) -> Result<(), ShaderError> {
+        let canonical = vfs::normalize(path).unwrap_or_else(|_| path.to_string());
+        let index = self.files.len();
+        self.files.push(ShaderSourceFile { path: path.to_string(), source: source.to_string() });
+        stack.push(canonical);
+
+        let directory = std::path::Path::new(path).parent().unwrap_or(std::path::Path::new(""));
+        for (i, line) in source.lines().enumerate() {
+            let Some(include) = line.trim().strip_prefix("#include") else {
+                self.code.push_str(line);
+                self.code.push('\n');
+                continue;
+            };
+            let name = include.trim().strip_prefix('"').and_then(|x| x.strip_suffix('"'))
+                .ok_or_else(|| ShaderError::preprocess(format!("{}:{}: expected #include \"file\"", path, i + 1)))?;
+            let include_path = directory.join(name).to_string_lossy().to_string();
+            let include_canonical = vfs::normalize(&include_path).ok();
+            // Files on the stack are also in `files`, so this has to come before the include once check
+            if include_canonical.as_ref().is_some_and(|x| stack.contains(x)) {
+                return Err(ShaderError::preprocess(format!("{}:{}: include cycle: {} -> {}", path, i + 1, stack.join(" -> "), include_path)));
+            }
+            let already_included = include_canonical.is_some()
+                && self.files.iter().any(|f| vfs::normalize(&f.path).ok() == include_canonical);
+            if !already_included {
+                let include_source = vfs::read_to_string(&include_path)
+                    .map_err(|e| ShaderError::preprocess(format!("{}:{}: failed to include \"{}\": {}", path, i + 1, include_path, e)))?;
+                self.code.push_str(&format!("#line 1 {}\n", self.files.len()));
+                self.include(&include_source, &include_path, stack)?;
+            }
+            self.code.push_str(&format!("#line {} {}\n", i + 2, index));
         }
+        stack.pop();
+        Ok(())
+    }
+}
 
-        let c_name = CString::new(name).unwrap();
-        let location = unsafe {
-            gl::GetUniformLocation(self.program, c_name.as_ptr())
+/// Splits a leading decimal number off `s`
+fn take_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+/// Parses "file:line(column): message" (Mesa), "file(line) : message" (NVIDIA)
+/// and "ERROR: file:line: message" (AMD, Intel) info log lines
+fn parse_log_line(log_line: &str) -> Option<(u32, u32, String)> {
+    let s = log_line.trim();
+    let s = s.strip_prefix("ERROR: ").or_else(|| s.strip_prefix("WARNING: ")).unwrap_or(s);
+    let (file, rest) = take_number(s)?;
+    let (line, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        let (line, rest) = take_number(rest)?;
+        // Optional column
+        let rest = match rest.strip_prefix('(') {
+            Some(x) => x.split_once(')')?.1,
+            None => rest,
+        };
+        (line, rest)
+    } else {
+        let (line, rest) = take_number(rest.strip_prefix('(')?)?;
+        (line, rest.strip_prefix(')')?)
+    };
+    let message = rest.trim_start().strip_prefix(':')?.trim();
+    Some((file, line, message.to_string()))
+}
+
+struct Program(GLuint);
+impl Drop for Program {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            unsafe { gl::DeleteProgram(self.0) };
+        }
+    }
+}
+
+/// Compiled shader objects, deleted with the last program linked from them
+struct CompiledStages(Vec<GLuint>);
+impl Drop for CompiledStages {
+    fn drop(&mut self) {
+        for &x in &self.0 {
+            unsafe { gl::DeleteShader(x) };
+        }
+    }
+}
+
+/// Clones share the same GL program, which is deleted with the last clone
+#[derive(Clone)]
+pub struct Shader{
+    program: Rc<Program>,
+    uniforms: HashMap<String, UniformInfo>,
+    uniform_blocks: HashMap<String, UniformBlockInfo>,
+}
+
+impl Shader {
+    pub fn create_new(vertex_shader: &str, fragment_shader:  &str) -> Result<Shader, ShaderError> {
+        Self::from_stages(&[
+            (ShaderStage::Vertex, &ShaderSource::from_code(vertex_shader)),
+            (ShaderStage::Fragment, &ShaderSource::from_code(fragment_shader)),
+        ])
+    }
+    pub fn from_stages(stages: &[(ShaderStage, &ShaderSource)]) -> Result<Shader, ShaderError> {
+        let mut x =Shader {
+            program : Rc::new(Program(0)),
+            uniforms: HashMap::new(),
+            uniform_blocks: HashMap::new(),
+        };
+        x.create_from_stages(stages)?;
+        Ok(x)
+    }
+    /// On failure the previously created program is kept
+    pub fn create(&mut self,vertex_shader: &str, fragment_shader:  &str) -> Result<(), ShaderError> {
+        self.create_from_stages(&[
+            (ShaderStage::Vertex, &ShaderSource::from_code(vertex_shader)),
+            (ShaderStage::Fragment, &ShaderSource::from_code(fragment_shader)),
+        ])
+    }
+    pub fn create_from_stages(&mut self, stages: &[(ShaderStage, &ShaderSource)]) -> Result<(), ShaderError> {
+        let program = Self::build_program(stages)?;
+        self.program = Rc::new(Program(program));
+        self.reflect();
+        Ok(())
+    }
+    fn build_program(stages: &[(ShaderStage, &ShaderSource)]) -> Result<GLuint, ShaderError> {
+        Self::link_stages(&Self::compile_stages(stages)?)
+    }
+    fn compile_stages(stages: &[(ShaderStage, &ShaderSource)]) -> Result<CompiledStages, ShaderError> {
+        // Stages compiled before a failing one are deleted with `shaders`
+        let mut shaders = CompiledStages(Vec::new());
+        for (stage, source) in stages {
+            shaders.0.push(Self::compile_shader(source, *stage)?);
+        }
+        Ok(shaders)
+    }
+    /// Links a new program, the stages are detached again so they can be linked into more programs
+    fn link_stages(shaders: &CompiledStages) -> Result<GLuint, ShaderError> {
+        unsafe {
+            let program = gl::CreateProgram();
+            for &x in &shaders.0 {
+                gl::AttachShader(program, x);
+            }
+            let link_res = Self::link_program(program);
+            for &x in &shaders.0 {
+                gl::DetachShader(program, x);
+            }
+            if let Err(e) = link_res {
+                gl::DeleteProgram(program);
+                return Err(e);
+            }
+            Ok(program)
+        }
+    }
+    fn from_program(program: GLuint) -> Shader {
+        let mut x = Shader {
+            program: Rc::new(Program(program)),
+            uniforms: HashMap::new(),
+            uniform_blocks: HashMap::new(),
         };
+        x.reflect();
+        x
+    }
+    pub fn bind(&self) {
+        unsafe { gl::UseProgram(self.program.0); };
+    }
 
-        if location == -1 {
-            println!("{} not found", name);
-            return 0;
+    /// Builds the uniform and uniform block tables of the linked program
+    fn reflect(&mut self) {
+        self.uniforms.clear();
+        self.uniform_blocks.clear();
+        let program = self.program.0;
+        if program == 0 {
+            return;
         }
+        unsafe {
+            let mut count: GLint = 0;
+            let mut max_length: GLint = 0;
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+            let mut buffer = vec![0u8; max_length.max(1) as usize];
+            for i in 0..count as GLuint {
+                let mut length: GLsizei = 0;
+                let mut size: GLint = 0;
+                let mut gl_type: GLenum = 0;
+                gl::GetActiveUniform(program, i, buffer.len() as GLsizei, &mut length, &mut size, &mut gl_type, buffer.as_mut_ptr() as *mut GLchar);
+                let name = String::from_utf8_lossy(&buffer[..length as usize]).to_string();
 
-        // Since self.uniform_location_cache is behind a reference,
-        // we need to clone the String to own it
-        self.uniform_location_cache.insert(name.to_string(), location);
-        location
+                let c_name = CString::new(name.as_str()).unwrap();
+                let location = gl::GetUniformLocation(program, c_name.as_ptr());
+                // Members of uniform blocks have no location
+                if location == -1 {
+                    continue;
+                }
+                // Arrays are reported as "name[0]"
+                let name = name.strip_suffix("[0]").map(str::to_string).unwrap_or(name);
+                self.uniforms.insert(name, UniformInfo { location, gl_type, size });
+            }
+
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCK_MAX_NAME_LENGTH, &mut max_length);
+            let mut buffer = vec![0u8; max_length.max(1) as usize];
+            for index in 0..count as GLuint {
+                let mut length: GLsizei = 0;
+                let mut data_size: GLint = 0;
+                gl::GetActiveUniformBlockName(program, index, buffer.len() as GLsizei, &mut length, buffer.as_mut_ptr() as *mut GLchar);
+                gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut data_size);
+                let name = String::from_utf8_lossy(&buffer[..length as usize]).to_string();
+                self.uniform_blocks.insert(name, UniformBlockInfo { index, data_size });
+            }
+        }
+    }
+    pub fn uniforms(&self) -> &HashMap<String, UniformInfo> {
+        &self.uniforms
+    }
+    pub fn uniform_blocks(&self) -> &HashMap<String, UniformBlockInfo> {
+        &self.uniform_blocks
+    }
+    pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.get(name)
+    }
+
+    /// Sets an active uniform, failing if it doesn't exist or `T` doesn't match its GLSL type
+    pub fn set_uniform<T: UniformValue>(&self, name: &str, value: &T) -> Result<(), String> {
+        let info = self.uniforms.get(name)
+            .ok_or_else(|| format!("Uniform \"{}\" is not an active uniform", name))?;
+        if !T::accepts(info.gl_type) {
+            return Err(format!("Uniform \"{}\" is a {}, got {}", name, uniform::type_name(info.gl_type), std::any::type_name::<T>()));
+        }
+        self.bind();
+        unsafe { value.set(info.location) };
+        Ok(())
     }
 
-    fn compile_shader(shader_source: &str, shader_type: GLenum) -> Result<GLuint, String> {
-        let shader = unsafe { gl::CreateShader(shader_type) };
-        
+    fn compile_shader(shader_source: &ShaderSource, stage: ShaderStage) -> Result<GLuint, ShaderError> {
         // Convert shader source to C string
-        let source = CString::new(shader_source)
-            .map_err(|e| format!("Failed to convert shader source to CString: {}", e))?;
-        
+        let source = CString::new(shader_source.code.as_str())
+            .map_err(|e| ShaderError::new(ShaderErrorKind::Compile(stage), format!("Failed to convert shader source to CString: {}", e), None))?;
+
         unsafe {
+            let shader = gl::CreateShader(stage.gl_enum());
             gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
             gl::CompileShader(shader);
 
@@ -102,25 +464,22 @@ impl Shader {
                 let mut len: GLint = 0;
                 gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
 
-                // Create buffer for error message
-                let mut buffer: Vec<u8> = Vec::with_capacity(len as usize);
-                buffer.set_len((len as usize) - 1); // subtract 1 to skip the null terminator
-
+                let mut buffer: Vec<u8> = vec![0; len.max(1) as usize];
                 gl::GetShaderInfoLog(
                     shader,
                     len,
                     ptr::null_mut(),
                     buffer.as_mut_ptr() as *mut GLchar
                 );
+                buffer.pop(); // skip the null terminator
 
                 gl::DeleteShader(shader);
-                return Err(String::from_utf8_lossy(&buffer).to_string());
+                return Err(ShaderError::new(ShaderErrorKind::Compile(stage), String::from_utf8_lossy(&buffer).to_string(), Some(shader_source)));
             }
+            Ok(shader)
         }
-
-        Ok(shader)
     }
-    fn link_program(program: GLuint) -> Result<(), String> {
+    fn link_program(program: GLuint) -> Result<(), ShaderError> {
         unsafe {
             gl::LinkProgram(program);
     
@@ -132,37 +491,199 @@ impl Shader {
                 let mut len: GLint = 0;
                 gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
     
-                // Create buffer for error message
-                let mut buffer: Vec<u8> = Vec::with_capacity(len as usize);
-                buffer.set_len((len as usize) - 1); // subtract 1 to skip the null terminator
-    
+                let mut buffer: Vec<u8> = vec![0; len.max(1) as usize];
                 gl::GetProgramInfoLog(
                     program,
                     len,
                     ptr::null_mut(),
                     buffer.as_mut_ptr() as *mut GLchar
                 );
+                buffer.pop(); // skip the null terminator
     
-                return Err(format!(
-                    "ERROR::SHADER::PROGRAM::LINKING_FAILED\n{}",
-                    String::from_utf8_lossy(&buffer)
-                ));
+                return Err(ShaderError::new(ShaderErrorKind::Link, String::from_utf8_lossy(&buffer).to_string(), None));
             }
     
             Ok(())
         }
     }
     pub fn bind_ubo(&self, name: &str, binding_point: GLuint) {
-        let c_str = std::ffi::CString::new(name).unwrap();
-        unsafe {
-            let block_index = gl::GetUniformBlockIndex(self.program, c_str.as_ptr());
-            gl::UniformBlockBinding(self.program, block_index, binding_point);
+        if let Some(block) = self.uniform_blocks.get(name) {
+            unsafe { gl::UniformBlockBinding(self.program.0, block.index, binding_point) };
         }
     }
     
 }
-impl Drop for Shader {
-    fn drop(&mut self) {
-        self.delete();
+struct CachedStages {
+    shaders: CompiledStages,
+    programs: Vec<Weak<Program>>,
+}
+
+/// Compiled stages keyed by their preprocessed sources, so variants sharing
+/// the same files and defines are only compiled once. Every variant still links
+/// its own program, uniforms set through one don't show up in another
+pub struct ShaderCache {
+    variants: HashMap<u64, CachedStages>,
+}
+
+impl Default for ShaderCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderCache {
+    pub fn new() -> Self {
+        Self { variants: HashMap::new() }
+    }
+
+    pub fn get_or_create(&mut self, stages: &[(ShaderStage, &ShaderSource)]) -> Result<Shader, ShaderError> {
+        let mut hasher = DefaultHasher::new();
+        for (stage, source) in stages {
+            (*stage as u32).hash(&mut hasher);
+            source.code.hash(&mut hasher);
+        }
+        let cached = match self.variants.entry(hasher.finish()) {
+            Entry::Occupied(x) => x.into_mut(),
+            Entry::Vacant(x) => x.insert(CachedStages { shaders: Shader::compile_stages(stages)?, programs: Vec::new() }),
+        };
+        let x = Shader::from_program(Shader::link_stages(&cached.shaders)?);
+        cached.programs.push(Rc::downgrade(&x.program));
+        Ok(x)
+    }
+
+    /// Drops compiled stages no live program was linked from
+    pub fn prune(&mut self) {
+        self.variants.retain(|_, x| {
+            x.programs.retain(|x| x.strong_count() > 0);
+            !x.programs.is_empty()
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::TempDir;
+
+    /// Writes `files` to a new temporary directory
+    fn write_files(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let directory = TempDir::new(name);
+        for (path, source) in files {
+            directory.write(path, source);
+        }
+        directory
+    }
+
+    fn preprocess_file(directory: &TempDir, path: &str, defines: &[(String, String)]) -> Result<ShaderSource, ShaderError> {
+        ShaderSource::from_file(&directory.file(path), defines)
+    }
+
+    /// Code lines without the `#line` directives
+    fn code_lines(source: &ShaderSource) -> Vec<&str> {
+        source.code.lines().filter(|x| !x.starts_with("#line")).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn includes_are_inlined_in_order_once() {
+        let directory = write_files("include-order", &[
+            ("main.glsl", "#version 330 core\n#include \"a.glsl\"\n#include \"b.glsl\"\nvoid main() {}\n"),
+            ("a.glsl", "// a\n"),
+            ("b.glsl", "#include \"a.glsl\"\n// b\n"),
+        ]);
+        let source = preprocess_file(&directory, "main.glsl", &[]).unwrap();
+
+        assert_eq!(code_lines(&source), ["#version 330 core", "// a", "// b", "void main() {}"]);
+        let files: Vec<&str> = source.files.iter().map(|x| x.path.rsplit('/').next().unwrap()).collect();
+        assert_eq!(files, ["main.glsl", "a.glsl", "b.glsl"]);
+        // Line directives point back at the including file after each include
+        assert!(source.code.contains("#line 1 1\n// a\n#line 3 0\n"), "{}", source.code);
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let directory = write_files("include-cycle", &[
+            ("a.glsl", "#include \"b.glsl\"\n"),
+            ("b.glsl", "#include \"a.glsl\"\n"),
+            ("self.glsl", "#include \"./self.glsl\"\n"),
+        ]);
+        let cycle = preprocess_file(&directory, "a.glsl", &[]).map(|_| ()).unwrap_err();
+        let self_include = preprocess_file(&directory, "self.glsl", &[]).map(|_| ()).unwrap_err();
+
+        assert_eq!(cycle.kind, ShaderErrorKind::Preprocess);
+        assert!(cycle.log.contains("include cycle") && cycle.log.ends_with("a.glsl"), "{}", cycle.log);
+        assert!(self_include.log.contains("include cycle"), "{}", self_include.log);
+    }
+
+    #[test]
+    fn include_errors() {
+        let directory = write_files("include-errors", &[
+            ("missing.glsl", "#include \"nothing.glsl\"\n"),
+            ("syntax.glsl", "\n#include <a.glsl>\n"),
+        ]);
+        let missing = preprocess_file(&directory, "missing.glsl", &[]).map(|_| ()).unwrap_err();
+        let syntax = preprocess_file(&directory, "syntax.glsl", &[]).map(|_| ()).unwrap_err();
+
+        assert!(missing.log.contains(":1: failed to include"), "{}", missing.log);
+        assert!(syntax.log.contains(":2: expected #include \"file\""), "{}", syntax.log);
+    }
+
+    #[test]
+    fn defines_follow_version() {
+        let defines = [("B".to_string(), String::new()), ("A".to_string(), "2".to_string())];
+        let source = ShaderSource::preprocess("#version 330 core\nvoid main() {}\n", "main.glsl", &defines).unwrap();
+        assert!(source.code.starts_with("#version 330 core\n#define A 2\n#define B \n#line 2 0\nvoid main"), "{}", source.code);
+
+        let source = ShaderSource::preprocess("void main() {}\n", "main.glsl", &defines).unwrap();
+        assert!(source.code.starts_with("#define A 2\n#define B \n#line 1 0\n"), "{}", source.code);
+    }
+
+    #[test]
+    fn log_lines_of_each_vendor() {
+        assert_eq!(parse_log_line("0:12(5): error: syntax error"), Some((0, 12, "error: syntax error".to_string())));
+        assert_eq!(parse_log_line("2:7: warning: unused"), Some((2, 7, "warning: unused".to_string())));
+        assert_eq!(parse_log_line("1(30) : error C0000: syntax error"), Some((1, 30, "error C0000: syntax error".to_string())));
+        assert_eq!(parse_log_line("ERROR: 0:4: 'x' : undeclared identifier"), Some((0, 4, "'x' : undeclared identifier".to_string())));
+        assert_eq!(parse_log_line("WARNING: 3:1: extension not supported"), Some((3, 1, "extension not supported".to_string())));
+        assert_eq!(parse_log_line("Link failed"), None);
+        assert_eq!(parse_log_line("0:12 missing colon"), None);
+    }
+
+    #[test]
+    fn errors_resolve_their_source_line() {
+        let source = ShaderSource::preprocess("void main() {\n    bad;\n}\n", "main.glsl", &[]).unwrap();
+        let error = ShaderError::new(ShaderErrorKind::Compile(ShaderStage::Fragment), "0:2(5): error: bad".to_string(), Some(&source));
+        assert_eq!(error.lines.len(), 1);
+        assert_eq!(error.lines[0].file.as_deref(), Some("main.glsl"));
+        assert_eq!(error.lines[0].source.as_deref(), Some("bad;"));
+    }
+
+    #[test]
+    fn cached_variants_keep_their_own_uniforms() {
+        let Some(_context) = crate::headless::HeadlessContext::for_test() else {
+            return;
+        };
+        let vertex = ShaderSource::from_code("#version 330 core\nuniform float value;\nvoid main() { gl_Position = vec4(value); }");
+        let fragment = ShaderSource::from_code("#version 330 core\nout vec4 color;\nvoid main() { color = vec4(1.0); }");
+        let stages = [(ShaderStage::Vertex, &vertex), (ShaderStage::Fragment, &fragment)];
+        let mut cache = ShaderCache::new();
+        let a = cache.get_or_create(&stages).unwrap();
+        let b = cache.get_or_create(&stages).unwrap();
+        assert_eq!(cache.variants.len(), 1);
+        assert_ne!(a.program.0, b.program.0);
+
+        a.set_uniform("value", &1.0f32).unwrap();
+        b.set_uniform("value", &2.0f32).unwrap();
+        let read = |x: &Shader| {
+            let mut value = 0.0f32;
+            unsafe { gl::GetUniformfv(x.program.0, x.uniform("value").unwrap().location, &mut value) };
+            value
+        };
+        assert_eq!((read(&a), read(&b)), (1.0, 2.0));
+
+        drop(a);
+        cache.prune();
+        assert_eq!(cache.variants.len(), 1);
+        drop(b);
+        cache.prune();
+        assert!(cache.variants.is_empty());
+    }
+}
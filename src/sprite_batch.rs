@@ -0,0 +1,285 @@
+use rgms_derive::MeshVertex;
+
+use crate::atlas::AtlasRegion;
+use crate::blend::BlendMode;
+use crate::handle::Handle;
+use crate::mesh::{Mesh, MeshVertex, PlanarTextureVertex, Topology};
+use crate::resource_manager::{ResourceManager, TextureHandle};
+use crate::sampler::Sampler;
+use crate::shader::Shader;
+
+#[repr(C)]
+#[derive(Clone, Copy, MeshVertex)]
+pub struct SpriteInstance {
+    /// mat3x2, one location per column
+    pub transform: [f32; 6],
+    pub uv_rect: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl SpriteInstance {
+    /// First location after the quad's `PlanarTextureVertex` attributes
+    pub const BASE_LOCATION: u32 = PlanarTextureVertex::LOCATIONS;
+
+    pub fn new(transform: &[f32; 6]) -> Self {
+        Self {
+            transform: *transform,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// Handles a sprite's keys resolved to, valid while the resource manager's revision is unchanged
+#[derive(Clone, Copy)]
+struct ResolvedSprite {
+    revision: u64,
+    texture: TextureHandle,
+    region: Option<AtlasRegion>,
+    shader: Handle<Shader>,
+    sampler: Option<Handle<Sampler>>,
+}
+
+/// Sprite component, the scene draws it at its entity's world transform
+#[derive(Clone)]
+pub struct Sprite {
+    /// Texture key or atlas region name
+    sprite: String,
+    shader: String,
+    sampler: Option<String>,
+    /// None picks `Opaque` for textures without alpha drawn at full opacity, so they batch by state, and `Alpha` otherwise
+    pub blend: Option<BlendMode>,
+    /// `uv_rect` is relative to the atlas region
+    pub instance: SpriteInstance,
+    resolved: Option<ResolvedSprite>,
+}
+
+impl Sprite {
+    pub fn new(sprite: &str) -> Self {
+        Self {
+            sprite: sprite.to_string(),
+            shader: SpriteBatch::DEFAULT_SHADER.to_string(),
+            sampler: None,
+            blend: None,
+            instance: SpriteInstance::new(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0]),
+            resolved: None,
+        }
+    }
+
+    pub fn set_shader(&mut self, key: &str) {
+        self.shader = key.to_string();
+        self.resolved = None;
+    }
+    pub fn set_sampler(&mut self, key: Option<&str>) {
+        self.sampler = key.map(|x| x.to_string());
+        self.resolved = None;
+    }
+
+    /// Looks the keys up again only once resources were added, replaced or unloaded
+    fn resolve_handles(&mut self, resource_manager: &ResourceManager) -> Option<ResolvedSprite> {
+        let revision = resource_manager.revision();
+        if let Some(x) = self.resolved.filter(|x| x.revision == revision) {
+            return Some(x);
+        }
+        let (texture, region) = resource_manager.resolve_sprite(&self.sprite)?;
+        let shader = resource_manager.handle::<Shader>(&self.shader)?;
+        let sampler = match &self.sampler {
+            Some(key) => Some(resource_manager.handle::<Sampler>(key)?),
+            None => None,
+        };
+        let resolved = ResolvedSprite { revision, texture, region, shader, sampler };
+        self.resolved = Some(resolved);
+        Some(resolved)
+    }
+
+    /// What to queue in the sprite batch, None while one of its resources isn't loaded
+    pub fn resolve(&mut self, resource_manager: &ResourceManager) -> Option<(SpriteState, SpriteInstance)> {
+        let ResolvedSprite { texture, region, shader, sampler, .. } = self.resolve_handles(resource_manager)?;
+        let mut instance = self.instance;
+        if let Some(region) = region {
+            instance.uv_rect = region.sub_rect(instance.uv_rect);
+        }
+        let blend = self.blend.unwrap_or_else(|| {
+            let has_alpha = resource_manager.texture(texture).and_then(|x| x.format()).is_none_or(|x| x.has_alpha());
+            if has_alpha || instance.color[3] < 1.0 { BlendMode::Alpha } else { BlendMode::Opaque }
+        });
+        Some((SpriteState { shader, texture, sampler, blend }, instance))
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct SpriteBatchStats {
+    pub draw_calls: u32,
+    pub sprites: u32,
+    pub vertices: u32,
+}
+
+/// Everything that has to match for sprites to share a draw call
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpriteState {
+    pub shader: Handle<Shader>,
+    pub texture: TextureHandle,
+    /// Sampler object overriding the texture's own sampling parameters
+    pub sampler: Option<Handle<Sampler>>,
+    pub blend: BlendMode,
+}
+
+struct BatchItem {
+    state: SpriteState,
+    instance: SpriteInstance,
+}
+
+pub struct SpriteBatch {
+    quad: Mesh,
+    items: Vec<BatchItem>,
+    upload: Vec<SpriteInstance>,
+    stats: SpriteBatchStats,
+}
+
+impl SpriteBatch {
+    pub const DEFAULT_SHADER: &'static str = "sprite_batch_shader";
+    const QUAD_VERTICES: i32 = 4;
+
+    // Creates its quad mesh, so it needs a GL context unlike a Default
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut batch = SpriteBatch {
+            quad: Mesh::new(),
+            items: Vec::new(),
+            upload: Vec::new(),
+            stats: SpriteBatchStats::default(),
+        };
+        batch.create();
+        batch
+    }
+
+    fn create(&mut self) {
+        self.quad.create::<_, u8>(Topology::TriangleStrip, &[
+            PlanarTextureVertex::new(-0.5, -0.5, 0.0, 0.0),
+            PlanarTextureVertex::new(0.5, -0.5, 1.0, 0.0),
+            PlanarTextureVertex::new(-0.5, 0.5, 0.0, 1.0),
+            PlanarTextureVertex::new(0.5, 0.5, 1.0, 1.0),
+        ], None);
+        unsafe { gl::BindVertexArray(0) };
+    }
+
+    pub fn draw(&mut self, state: SpriteState, instance: SpriteInstance) {
+        self.items.push(BatchItem {
+            state,
+            instance,
+        });
+    }
+
+    /// Draws every queued sprite, one instanced call per state.
+    /// `bind_state` binds the shader, texture and sampler of a state, returning false if one is missing.
+    /// Leaves the blend state of the last group applied
+    pub fn flush(&mut self, mut bind_state: impl FnMut(&SpriteState) -> bool) {
+        if self.items.is_empty() {
+            return;
+        }
+        // Opaque sprites go first, sorted by state. Blended sprites keep their submission order
+        // so they composite back to front, only consecutive sprites sharing a state are merged
+        self.items.sort_by(|a, b| match (a.state.blend.is_blended(), b.state.blend.is_blended()) {
+            (false, false) => (a.state.shader, a.state.texture, a.state.sampler).cmp(&(b.state.shader, b.state.texture, b.state.sampler)),
+            (a, b) => a.cmp(&b),
+        });
+        self.upload.clear();
+        self.upload.extend(self.items.iter().map(|x| x.instance));
+
+        self.quad.set_instances(&self.upload, SpriteInstance::BASE_LOCATION, gl::STREAM_DRAW);
+
+        let mut start = 0;
+        let mut blend = None;
+        while start < self.items.len() {
+            let first = &self.items[start].state;
+            let end = start + self.items[start..].iter()
+                .take_while(|x| x.state == *first)
+                .count();
+            let count = (end - start) as i32;
+
+            if blend != Some(first.blend) {
+                first.blend.apply();
+                blend = Some(first.blend);
+            }
+            if bind_state(first) {
+                self.quad.draw_instanced_range(start, count);
+                self.stats.draw_calls += 1;
+                self.stats.sprites += count as u32;
+                self.stats.vertices += (count * Self::QUAD_VERTICES) as u32;
+            } else {
+                println!("Sprite batch skipped {} sprite(s), removed shader {:?}, texture {:?} or sampler {:?}", count, first.shader, first.texture, first.sampler);
+            }
+            start = end;
+        }
+        self.items.clear();
+        unsafe { gl::BindVertexArray(0) };
+    }
+
+    pub fn stats(&self) -> SpriteBatchStats {
+        self.stats
+    }
+    pub fn reset_stats(&mut self) {
+        self.stats = SpriteBatchStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+    use crate::sampler::SamplerDesc;
+    use crate::texture::Texture;
+
+    const VERTEX: &str = "#version 330 core\nvoid main() { gl_Position = vec4(0.0); }";
+    const FRAGMENT: &str = "#version 330 core\nout vec4 color;\nvoid main() { color = vec4(1.0); }";
+
+    #[test]
+    fn sprite_instance_layout() {
+        // mat3x2 takes a location per column
+        assert_eq!(SpriteInstance::LOCATIONS, 5);
+        let Some(_context) = HeadlessContext::for_test() else {
+            return;
+        };
+        let mut mesh = Mesh::new();
+        mesh.create::<_, u8>(Topology::TriangleStrip, &[PlanarTextureVertex::new(0.0, 0.0, 0.0, 0.0)], None);
+        mesh.set_instances(&[SpriteInstance::new(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0])], SpriteInstance::BASE_LOCATION, gl::STATIC_DRAW);
+        mesh.bind();
+        let attribute = |location: u32| unsafe {
+            let (mut size, mut stride, mut divisor) = (0, 0, 0);
+            let mut pointer: *mut std::ffi::c_void = std::ptr::null_mut();
+            gl::GetVertexAttribiv(location, gl::VERTEX_ATTRIB_ARRAY_SIZE, &mut size);
+            gl::GetVertexAttribiv(location, gl::VERTEX_ATTRIB_ARRAY_STRIDE, &mut stride);
+            gl::GetVertexAttribiv(location, gl::VERTEX_ATTRIB_ARRAY_DIVISOR, &mut divisor);
+            // The binding takes the output as a const pointer
+            gl::GetVertexAttribPointerv(location, gl::VERTEX_ATTRIB_ARRAY_POINTER, (&raw mut pointer).cast_const());
+            (size, pointer as usize, stride, divisor)
+        };
+        let layout: Vec<_> = (0..SpriteInstance::LOCATIONS).map(|x| attribute(SpriteInstance::BASE_LOCATION + x)).collect();
+        assert_eq!(layout, [(2, 0, 56, 1), (2, 8, 56, 1), (2, 16, 56, 1), (4, 24, 56, 1), (4, 40, 56, 1)]);
+        // The quad's own attributes stay per vertex
+        assert_eq!(attribute(1), (2, 8, 16, 0));
+    }
+
+    #[test]
+    fn cached_handles_follow_replaced_keys() {
+        let Some(_context) = HeadlessContext::for_test() else {
+            return;
+        };
+        let mut rm = ResourceManager::new();
+        let texture = rm.add_resource("texture", Texture::create_new(gl::TEXTURE_2D));
+        rm.add_resource("shader", Shader::create_new(VERTEX, FRAGMENT).unwrap());
+        let mut sprite = Sprite::new("texture");
+        assert!(sprite.resolve(&rm).is_none());
+
+        sprite.set_shader("shader");
+        let (state, _) = sprite.resolve(&rm).unwrap();
+        assert_eq!(state.texture, TextureHandle::Texture(texture));
+        let revision = rm.revision();
+        assert!(sprite.resolve(&rm).is_some());
+        assert_eq!(rm.revision(), revision);
+
+        // A key now naming another type of resource isn't a texture anymore
+        rm.add_resource("texture", Sampler::create_new(&SamplerDesc::default()));
+        assert!(sprite.resolve(&rm).is_none());
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+
+/// Temporary directory for a test, removed when dropped, so also when the test panics
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` has to be unique among the tests, they run in parallel
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("rgms-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+    pub(crate) fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+    /// Path as the string the loaders take
+    pub(crate) fn file(&self, name: &str) -> String {
+        self.join(name).to_str().unwrap().to_string()
+    }
+
+    /// Writes `name`, creating its directories, returns its path
+    pub(crate) fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = self.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        self.file(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
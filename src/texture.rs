@@ -1,9 +1,17 @@
+use std::ffi::c_void;
+
 use gl::types::{GLenum, GLint, GLsizei};
-use image::RgbImage;
+use image::{ColorType, DynamicImage};
+
+use crate::sampler::{MipmapMode, SamplerDesc};
+use crate::vfs;
 
 pub struct Texture{
     handle: u32,
     texture_type: GLenum,
+    sampler: SamplerDesc,
+    /// None until an image is uploaded
+    format: Option<TextureFormat>,
 }
 impl Texture {
     /*pub fn new(texture_type: GLenum) -> Texture {
@@ -16,6 +24,8 @@ impl Texture {
         let mut x =Texture {
             handle : 0,
             texture_type,
+            sampler: SamplerDesc::default(),
+            format: None,
         };
         x.create();
         x
@@ -26,6 +36,22 @@ impl Texture {
             gl::GenTextures(1, &mut self.handle);
         }
     }
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+    pub fn texture_type(&self) -> GLenum {
+        self.texture_type
+    }
+    pub fn format(&self) -> Option<TextureFormat> {
+        self.format
+    }
+    /// Mipmaps are generated on upload, so a desc using them has to be set before `set_texture`
+    pub fn set_sampler(&mut self, desc: &SamplerDesc) {
+        self.sampler = *desc;
+        self.bind(gl::TEXTURE0);
+        desc.apply_to_texture(self.texture_type);
+        unsafe { gl::BindTexture(self.texture_type, 0) };
+    }
     pub fn bind(&self, texture_unit : GLenum) {
         unsafe { gl::ActiveTexture(texture_unit); gl::BindTexture(self.texture_type,self.handle); };
     }
@@ -35,32 +61,379 @@ impl Texture {
             self.handle = 0;
         }
     }
-    pub fn set_texture_rgb(&mut self,image: &RgbImage){
-        self.set_texture(gl::RGB, image.width() as i32, image.height() as i32, gl::RGB, image.as_raw().as_ptr());
+    pub fn set_image(&mut self, image: &TextureImage) {
+        self.set_texture(image.format, image.width as i32, image.height as i32, Some(&image.pixels));
     }
-    /*
-    pub fn set_texture_rgba(&mut self,image: &RgbaImage){
-        self.set_texture(gl::RGBA, image.width() as i32, image.height() as i32, gl::RGBA, image.as_raw().as_ptr());
-    }*/
-    pub fn set_texture(&mut self,internal_format : GLenum, width : GLsizei, height : GLsizei, format : GLenum, data: *const u8){
-        self.set_texture_wo_mipmap(internal_format,width,height,format,data);
+    /// Generates mipmaps if the sampler uses them
+    pub fn set_texture(&mut self,format : TextureFormat, width : GLsizei, height : GLsizei, pixels: Option<&TexturePixels>){
+        self.set_texture_wo_mipmap(format,width,height,pixels);
+        if self.sampler.mipmap_mode == MipmapMode::None {
+            return;
+        }
         self.bind(gl::TEXTURE0);
         unsafe {
             gl::GenerateMipmap(self.texture_type);
             gl::BindTexture(self.texture_type, 0);
         }
     }
-    pub fn set_texture_wo_mipmap(&mut self,internal_format : GLenum, width : GLsizei, height : GLsizei, format : GLenum, data: *const u8){
+    /// Without `pixels` the contents are undefined, e.g. for render targets.
+    /// Panics if `pixels` holds less than `width * height` pixels of `format`
+    pub fn set_texture_wo_mipmap(&mut self,format : TextureFormat, width : GLsizei, height : GLsizei, pixels: Option<&TexturePixels>){
+        let data = match pixels {
+            Some(x) => {
+                let size = width.max(0) as usize * height.max(0) as usize * format.pixel_size();
+                assert!(x.byte_len() >= size, "{}x{} {:?} texture needs {} bytes, got {}", width, height, format, size, x.byte_len());
+                x.as_ptr()
+            }
+            None => std::ptr::null(),
+        };
+        self.format = Some(format);
         self.bind(gl::TEXTURE0);
         unsafe {
-            gl::TexImage2D(self.texture_type, 0, internal_format as GLint, width, height, 0, format, gl::UNSIGNED_BYTE, data as *const _);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, unpack_alignment(width as usize * format.pixel_size()));
+            gl::TexImage2D(self.texture_type, 0, format.internal_format() as GLint, width, height, 0, format.pixel_format(), format.data_type(), data);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::TexParameteriv(self.texture_type, gl::TEXTURE_SWIZZLE_RGBA, format.swizzle().as_ptr());
+            self.sampler.apply_to_texture(self.texture_type);
             gl::BindTexture(self.texture_type, 0);
         }
     }
     
 }
+/// Rows are tightly packed, the default alignment of 4 breaks e.g. odd width RGB or R8 images.
+/// The largest alignment dividing the row size, so every row starts where GL expects it
+fn unpack_alignment(row_size: usize) -> GLint {
+    [8, 4, 2].into_iter().find(|&x| row_size.is_multiple_of(x as usize)).unwrap_or(1)
+}
+
 impl Drop for Texture {
     fn drop(&mut self) {
         self.delete();
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    R8,
+    Rg8,
+    Rgb8,
+    Rgba8,
+    Srgb8,
+    Srgb8Alpha8,
+    Rgba16F,
+}
+
+impl TextureFormat {
+    pub fn internal_format(self) -> GLenum {
+        match self {
+            TextureFormat::R8 => gl::R8,
+            TextureFormat::Rg8 => gl::RG8,
+            TextureFormat::Rgb8 => gl::RGB8,
+            TextureFormat::Rgba8 => gl::RGBA8,
+            TextureFormat::Srgb8 => gl::SRGB8,
+            TextureFormat::Srgb8Alpha8 => gl::SRGB8_ALPHA8,
+            TextureFormat::Rgba16F => gl::RGBA16F,
+        }
+    }
+    pub fn pixel_format(self) -> GLenum {
+        match self {
+            TextureFormat::R8 => gl::RED,
+            TextureFormat::Rg8 => gl::RG,
+            TextureFormat::Rgb8 | TextureFormat::Srgb8 => gl::RGB,
+            TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8 | TextureFormat::Rgba16F => gl::RGBA,
+        }
+    }
+    /// Type of the uploaded data, half floats are uploaded from f32 and converted by the driver
+    pub fn data_type(self) -> GLenum {
+        match self {
+            TextureFormat::Rgba16F => gl::FLOAT,
+            _ => gl::UNSIGNED_BYTE,
+        }
+    }
+    /// Grayscale images are stored in R8/RG8, swizzled back to luminance (and alpha) when sampled
+    pub fn swizzle(self) -> [GLint; 4] {
+        let (r, g, b, a) = match self {
+            TextureFormat::R8 => (gl::RED, gl::RED, gl::RED, gl::ONE),
+            TextureFormat::Rg8 => (gl::RED, gl::RED, gl::RED, gl::GREEN),
+            _ => (gl::RED, gl::GREEN, gl::BLUE, gl::ALPHA),
+        };
+        [r as GLint, g as GLint, b as GLint, a as GLint]
+    }
+    pub fn has_alpha(self) -> bool {
+        matches!(self, TextureFormat::Rg8 | TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8 | TextureFormat::Rgba16F)
+    }
+    /// Size of an uploaded pixel in bytes
+    pub fn pixel_size(self) -> usize {
+        match self {
+            TextureFormat::R8 => 1,
+            TextureFormat::Rg8 => 2,
+            TextureFormat::Rgb8 | TextureFormat::Srgb8 => 3,
+            TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8 => 4,
+            TextureFormat::Rgba16F => 16,
+        }
+    }
+}
+
+pub enum TexturePixels {
+    U8(Vec<u8>),
+    F32(Vec<f32>),
+}
+
+impl TexturePixels {
+    fn byte_len(&self) -> usize {
+        match self {
+            TexturePixels::U8(x) => x.len(),
+            TexturePixels::F32(x) => std::mem::size_of_val(x.as_slice()),
+        }
+    }
+    fn as_ptr(&self) -> *const c_void {
+        match self {
+            TexturePixels::U8(x) => x.as_ptr() as *const c_void,
+            TexturePixels::F32(x) => x.as_ptr() as *const c_void,
+        }
+    }
+}
+
+/// Decoded image ready for upload
+pub struct TextureImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: TexturePixels,
+}
+
+impl TextureImage {
+    /// Picks the format from the image's channels, `srgb` applies to color images (not R8/RG8 data or HDR).
+    /// 16-bit images are reduced to 8 bits per channel, there are no 16-bit formats
+    pub fn from_image(image: DynamicImage, srgb: bool) -> TextureImage {
+        let (width, height) = (image.width(), image.height());
+        let (format, pixels) = match image.color() {
+            ColorType::L8 | ColorType::L16 => (TextureFormat::R8, TexturePixels::U8(image.into_luma8().into_raw())),
+            ColorType::La8 | ColorType::La16 => (TextureFormat::Rg8, TexturePixels::U8(image.into_luma_alpha8().into_raw())),
+            ColorType::Rgb8 | ColorType::Rgb16 => (
+                if srgb { TextureFormat::Srgb8 } else { TextureFormat::Rgb8 },
+                TexturePixels::U8(image.into_rgb8().into_raw()),
+            ),
+            ColorType::Rgb32F | ColorType::Rgba32F => (TextureFormat::Rgba16F, TexturePixels::F32(image.into_rgba32f().into_raw())),
+            _ => (
+                if srgb { TextureFormat::Srgb8Alpha8 } else { TextureFormat::Rgba8 },
+                TexturePixels::U8(image.into_rgba8().into_raw()),
+            ),
+        };
+        TextureImage { format, width, height, pixels }
+    }
+
+    /// Inverse of `from_image`, rows stay in the order they are stored in
+    pub fn into_image(self) -> Result<DynamicImage, String> {
+        let (width, height) = (self.width, self.height);
+        let image = match (self.format, self.pixels) {
+            (TextureFormat::R8, TexturePixels::U8(x)) => image::GrayImage::from_raw(width, height, x).map(DynamicImage::ImageLuma8),
+            (TextureFormat::Rg8, TexturePixels::U8(x)) => image::GrayAlphaImage::from_raw(width, height, x).map(DynamicImage::ImageLumaA8),
+            (TextureFormat::Rgb8 | TextureFormat::Srgb8, TexturePixels::U8(x)) => image::RgbImage::from_raw(width, height, x).map(DynamicImage::ImageRgb8),
+            (TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8, TexturePixels::U8(x)) => image::RgbaImage::from_raw(width, height, x).map(DynamicImage::ImageRgba8),
+            (TextureFormat::Rgba16F, TexturePixels::F32(x)) => image::Rgba32FImage::from_raw(width, height, x).map(DynamicImage::ImageRgba32F),
+            _ => None,
+        };
+        image.ok_or_else(|| "pixel data doesn't match the format".to_string())
+    }
+
+    /// Uses the sRGB variant of a color format, images are baked without it so one file serves both
+    fn with_srgb(mut self, srgb: bool) -> TextureImage {
+        if srgb {
+            self.format = match self.format {
+                TextureFormat::Rgb8 => TextureFormat::Srgb8,
+                TextureFormat::Rgba8 => TextureFormat::Srgb8Alpha8,
+                x => x,
+            };
+        }
+        self
+    }
+
+    /// Serializes the image for `from_baked`:
+    /// magic, format, width and height (little endian u32), then the pixels (f32 as little endian)
+    pub fn to_baked(&self) -> Vec<u8> {
+        let format = BAKED_FORMATS.iter().position(|x| *x == self.format).unwrap() as u8;
+        let mut data = Vec::new();
+        data.extend_from_slice(BAKED_MAGIC);
+        data.extend_from_slice(&[format, 0, 0, 0]);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        match &self.pixels {
+            TexturePixels::U8(x) => data.extend_from_slice(x),
+            TexturePixels::F32(x) => data.extend(x.iter().flat_map(|x| x.to_le_bytes())),
+        }
+        data
+    }
+
+    pub fn from_baked(data: &[u8]) -> Result<TextureImage, String> {
+        let header = data.get(..16).filter(|x| x.starts_with(BAKED_MAGIC)).ok_or("missing header")?;
+        let format = *BAKED_FORMATS.get(header[4] as usize).ok_or_else(|| format!("unknown format {}", header[4]))?;
+        let width = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let height = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let pixels = &data[16..];
+        if pixels.len() != width as usize * height as usize * format.pixel_size() {
+            return Err(format!("expected {}x{} {:?} pixels, got {} bytes", width, height, format, pixels.len()));
+        }
+        let pixels = match format {
+            TextureFormat::Rgba16F => TexturePixels::F32(pixels.chunks_exact(4).map(|x| f32::from_le_bytes(x.try_into().unwrap())).collect()),
+            _ => TexturePixels::U8(pixels.to_vec()),
+        };
+        Ok(TextureImage { format, width, height, pixels })
+    }
+}
+
+const BAKED_MAGIC: &[u8; 4] = b"RGTX";
+/// Index is the format byte of a baked image, append only
+const BAKED_FORMATS: [TextureFormat; 7] = [
+    TextureFormat::R8,
+    TextureFormat::Rg8,
+    TextureFormat::Rgb8,
+    TextureFormat::Rgba8,
+    TextureFormat::Srgb8,
+    TextureFormat::Srgb8Alpha8,
+    TextureFormat::Rgba16F,
+];
+
+pub fn is_baked(data: &[u8]) -> bool {
+    data.starts_with(BAKED_MAGIC)
+}
+
+fn read_baked(path: &str, data: &[u8]) -> Result<TextureImage, String> {
+    TextureImage::from_baked(data).map_err(|e| format!("Invalid baked image \"{}\": {}", path, e))
+}
+
+/// Reads and decodes an image through the VFS
+pub fn decode_image(path: &str) -> Result<DynamicImage, String> {
+    let data = vfs::read(path).map_err(|e| format!("Failed to read \"{}\": {}", path, e))?;
+    if is_baked(&data) {
+        // Baked images are stored flipped for upload
+        return read_baked(path, &data)?.into_image()
+            .map(|x| x.flipv())
+            .map_err(|e| format!("Invalid baked image \"{}\": {}", path, e));
+    }
+    image::load_from_memory(&data).map_err(|e| format!("\"{}\" is not a supported image: {}", path, e))
+}
+
+/// Opens an image flipped so the first row is the bottom one, as OpenGL expects.
+/// Images baked by rgms-pack are already in that layout and skip decoding
+pub fn open_image(path: &str, srgb: bool) -> Result<TextureImage, String> {
+    let data = vfs::read(path).map_err(|e| format!("Failed to read \"{}\": {}", path, e))?;
+    if is_baked(&data) {
+        return read_baked(path, &data).map(|x| x.with_srgb(srgb));
+    }
+    image::load_from_memory(&data)
+        .map(|x| TextureImage::from_image(x.flipv(), srgb))
+        .map_err(|e| format!("\"{}\" is not a supported image: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(image: DynamicImage) {
+        let texture = TextureImage::from_image(image.clone(), false);
+        let format = texture.format;
+        let baked = TextureImage::from_baked(&texture.to_baked()).unwrap();
+        assert_eq!(baked.format, format);
+        assert_eq!((baked.width, baked.height), (image.width(), image.height()));
+        assert_eq!(baked.into_image().unwrap(), TextureImage::from_image(image, false).into_image().unwrap());
+    }
+
+    fn format_of(image: DynamicImage, srgb: bool) -> TextureFormat {
+        TextureImage::from_image(image, srgb).format
+    }
+
+    #[test]
+    fn luma_is_r8() {
+        assert_eq!(format_of(DynamicImage::new_luma8(1, 1), false), TextureFormat::R8);
+        // Grayscale is data, it never becomes sRGB
+        assert_eq!(format_of(DynamicImage::new_luma8(1, 1), true), TextureFormat::R8);
+    }
+
+    #[test]
+    fn luma_alpha_is_rg8() {
+        assert_eq!(format_of(DynamicImage::new_luma_a8(1, 1), false), TextureFormat::Rg8);
+        assert_eq!(format_of(DynamicImage::new_luma_a8(1, 1), true), TextureFormat::Rg8);
+    }
+
+    #[test]
+    fn rgb_is_rgb8() {
+        assert_eq!(format_of(DynamicImage::new_rgb8(1, 1), false), TextureFormat::Rgb8);
+        assert_eq!(format_of(DynamicImage::new_rgb8(1, 1), true), TextureFormat::Srgb8);
+    }
+
+    #[test]
+    fn rgba_is_rgba8() {
+        assert_eq!(format_of(DynamicImage::new_rgba8(1, 1), false), TextureFormat::Rgba8);
+        assert_eq!(format_of(DynamicImage::new_rgba8(1, 1), true), TextureFormat::Srgb8Alpha8);
+    }
+
+    #[test]
+    fn float_is_rgba16f() {
+        for image in [DynamicImage::new_rgb32f(1, 1), DynamicImage::new_rgba32f(1, 1)] {
+            let texture = TextureImage::from_image(image, true);
+            assert_eq!(texture.format, TextureFormat::Rgba16F);
+            assert!(matches!(texture.pixels, TexturePixels::F32(x) if x.len() == 4));
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_is_reduced_to_eight_bit() {
+        let cases = [
+            (DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(1, 1, image::Luma([0xFFFF]))), TextureFormat::R8, vec![255]),
+            (DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(1, 1, image::LumaA([0xFFFF, 0]))), TextureFormat::Rg8, vec![255, 0]),
+            (DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(1, 1, image::Rgb([0xFFFF, 0x8080, 0]))), TextureFormat::Rgb8, vec![255, 128, 0]),
+            (DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(1, 1, image::Rgba([0, 0, 0xFFFF, 0x8080]))), TextureFormat::Rgba8, vec![0, 0, 255, 128]),
+        ];
+        for (image, format, pixels) in cases {
+            let texture = TextureImage::from_image(image, false);
+            assert_eq!(texture.format, format);
+            assert!(matches!(texture.pixels, TexturePixels::U8(x) if x == pixels), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn unpack_alignment_divides_the_row() {
+        // Odd width R8 and RGB rows
+        assert_eq!(unpack_alignment(3), 1);
+        assert_eq!(unpack_alignment(3 * 3), 1);
+        assert_eq!(unpack_alignment(2 * 3), 2);
+        assert_eq!(unpack_alignment(3 * 4), 4);
+        assert_eq!(unpack_alignment(2 * 4), 8);
+        assert_eq!(unpack_alignment(16), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "needs 12 bytes, got 9")]
+    fn short_pixel_data_is_rejected() {
+        // Checked before any GL call, so no context is needed
+        let mut texture = Texture { handle: 0, texture_type: gl::TEXTURE_2D, sampler: SamplerDesc::default(), format: None };
+        texture.set_texture_wo_mipmap(TextureFormat::Rgb8, 2, 2, Some(&TexturePixels::U8(vec![0; 9])));
+    }
+
+    #[test]
+    fn baked_round_trip() {
+        round_trip(DynamicImage::ImageLuma8(image::GrayImage::from_fn(3, 2, |x, y| image::Luma([(x * 10 + y) as u8]))));
+        round_trip(DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(2, 2, |x, y| image::LumaA([x as u8, y as u8]))));
+        round_trip(DynamicImage::ImageRgb8(image::RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8, y as u8, 7]))));
+        round_trip(DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 4, |x, y| image::Rgba([x as u8, y as u8, 1, 200]))));
+        round_trip(DynamicImage::ImageRgba32F(image::Rgba32FImage::from_fn(2, 3, |x, y| image::Rgba([x as f32 * 0.5, y as f32, -1.25, 1.0]))));
+    }
+
+    #[test]
+    fn baked_srgb_is_applied_on_load() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(1, 1));
+        let baked = TextureImage::from_image(image, false).to_baked();
+        assert_eq!(TextureImage::from_baked(&baked).unwrap().with_srgb(true).format, TextureFormat::Srgb8Alpha8);
+    }
+
+    #[test]
+    fn invalid_baked_data() {
+        assert!(TextureImage::from_baked(b"PNG").is_err());
+        let mut baked = TextureImage::from_image(DynamicImage::ImageRgb8(image::RgbImage::new(2, 2)), false).to_baked();
+        baked.pop();
+        assert!(TextureImage::from_baked(&baked).is_err());
+        baked[4] = 200;
+        assert!(TextureImage::from_baked(&baked).is_err());
+    }
+}
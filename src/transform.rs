@@ -10,23 +10,41 @@ pub struct Transform2D {
     update_required: bool,
 }
 
+impl Default for Transform2D {
+    fn default() -> Self {
+        Self {
+            scale: Vec2::new(1.0, 1.0),
+            radians: 0.0,
+            transform_matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            update_required: false,
+        }
+    }
+}
+
 impl Transform2D {
     pub fn new(position: Vec2, scale: Vec2, radians: f32) -> Self {
         let mut transform = Self {
             scale,
             radians,
             transform_matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
-            update_required: false,
+            update_required: true,
         };
         transform.set_position(position);
         transform
     }
 
-    pub fn default() -> Self {
+    /// Keeps `matrix` as is, scale and rotation are read back from it (a shear only lives in the matrix)
+    pub fn from_matrix(matrix: [f32; 6]) -> Self {
+        let scale_x = matrix[0].hypot(matrix[1]);
+        let scale_y = if scale_x != 0.0 {
+            (matrix[0] * matrix[3] - matrix[1] * matrix[2]) / scale_x
+        } else {
+            matrix[2].hypot(matrix[3])
+        };
         Self {
-            scale: Vec2::new(1.0, 1.0),
-            radians: 0.0,
-            transform_matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            scale: Vec2::new(scale_x, scale_y),
+            radians: matrix[1].atan2(matrix[0]),
+            transform_matrix: matrix,
             update_required: false,
         }
     }
@@ -117,4 +135,62 @@ impl Transform2D {
     pub fn set_angle(&mut self, angle: f32) {
         self.set_radians((angle / 180.0) * PI);
     }
-}
\ No newline at end of file
+}
+
+/// `local` placed in `parent`'s space, both are column major 2x3 matrices
+pub fn combine(parent: &[f32; 6], local: &[f32; 6]) -> [f32; 6] {
+    [
+        parent[0] * local[0] + parent[2] * local[1],
+        parent[1] * local[0] + parent[3] * local[1],
+        parent[0] * local[2] + parent[2] * local[3],
+        parent[1] * local[2] + parent[3] * local[3],
+        parent[0] * local[4] + parent[2] * local[5] + parent[4],
+        parent[1] * local[4] + parent[3] * local[5] + parent[5],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f32; 6], expected: &[f32; 6]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn combine_applies_local_then_parent() {
+        let identity = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let parent = *Transform2D::new(vec2(10.0, 0.0), vec2(2.0, 2.0), PI / 2.0).transformation_matrix();
+        let local = *Transform2D::new(vec2(1.0, 0.0), vec2(1.0, 1.0), 0.0).transformation_matrix();
+
+        assert_close(&combine(&identity, &local), &local);
+        assert_close(&combine(&parent, &identity), &parent);
+        // The local offset is scaled by 2 and turned a quarter, then moved by the parent's position
+        assert_close(&combine(&parent, &local), &[0.0, 2.0, -2.0, 0.0, 10.0, 2.0]);
+    }
+
+    #[test]
+    fn from_matrix_reads_back_scale_and_rotation() {
+        let mut transform = Transform2D::new(vec2(3.0, -4.0), vec2(2.0, 0.5), 0.3);
+        let matrix = *transform.transformation_matrix();
+        let mut read_back = Transform2D::from_matrix(matrix);
+        assert!((read_back.radians() - 0.3).abs() < 1e-5);
+        assert!((read_back.scale() - vec2(2.0, 0.5)).length() < 1e-5);
+        assert_eq!(read_back.position(), vec2(3.0, -4.0));
+        assert_eq!(read_back.transformation_matrix(), &matrix);
+    }
+
+    #[test]
+    fn from_matrix_keeps_mirroring_in_the_y_scale() {
+        let mut mirrored = Transform2D::from_matrix([1.0, 0.0, 0.0, -3.0, 0.0, 0.0]);
+        assert_eq!(mirrored.scale(), vec2(1.0, -3.0));
+        assert_eq!(mirrored.radians(), 0.0);
+        assert_eq!(mirrored.transformation_matrix(), &[1.0, 0.0, 0.0, -3.0, 0.0, 0.0]);
+
+        // A zero x scale falls back to the length of the y axis
+        let flat = Transform2D::from_matrix([0.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+        assert_eq!(flat.scale(), vec2(0.0, 2.0));
+    }
+}
@@ -0,0 +1,115 @@
+use gl::types::GLenum;
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+
+/// Values that can be assigned to a uniform, checked against the reflected GLSL type
+pub trait UniformValue {
+    fn accepts(gl_type: GLenum) -> bool;
+    /// # Safety
+    /// Needs a current GL context with the program owning `location` bound, and `accepts` true for its type
+    unsafe fn set(&self, location: i32);
+}
+
+pub fn is_sampler(gl_type: GLenum) -> bool {
+    matches!(gl_type,
+        gl::SAMPLER_1D | gl::SAMPLER_2D | gl::SAMPLER_3D | gl::SAMPLER_CUBE
+        | gl::SAMPLER_1D_SHADOW | gl::SAMPLER_2D_SHADOW | gl::SAMPLER_CUBE_SHADOW
+        | gl::SAMPLER_1D_ARRAY | gl::SAMPLER_2D_ARRAY | gl::SAMPLER_2D_ARRAY_SHADOW
+        | gl::SAMPLER_2D_MULTISAMPLE | gl::SAMPLER_2D_RECT | gl::SAMPLER_BUFFER
+        | gl::INT_SAMPLER_2D | gl::INT_SAMPLER_3D | gl::INT_SAMPLER_2D_ARRAY
+        | gl::UNSIGNED_INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_3D | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY)
+}
+
+pub fn type_name(gl_type: GLenum) -> &'static str {
+    match gl_type {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::INT => "int",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT => "uint",
+        gl::BOOL => "bool",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::FLOAT_MAT3x2 => "mat3x2",
+        x if is_sampler(x) => "sampler",
+        _ => "unsupported",
+    }
+}
+
+impl UniformValue for f32 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT }
+    unsafe fn set(&self, location: i32) { gl::Uniform1f(location, *self) }
+}
+impl UniformValue for [f32; 2] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC2 }
+    unsafe fn set(&self, location: i32) { gl::Uniform2fv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for [f32; 3] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC3 }
+    unsafe fn set(&self, location: i32) { gl::Uniform3fv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for [f32; 4] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC4 }
+    unsafe fn set(&self, location: i32) { gl::Uniform4fv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for Vec2 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC2 }
+    unsafe fn set(&self, location: i32) { gl::Uniform2f(location, self.x, self.y) }
+}
+impl UniformValue for Vec3 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC3 }
+    unsafe fn set(&self, location: i32) { gl::Uniform3f(location, self.x, self.y, self.z) }
+}
+impl UniformValue for Vec4 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_VEC4 }
+    unsafe fn set(&self, location: i32) { gl::Uniform4f(location, self.x, self.y, self.z, self.w) }
+}
+/// Also used for samplers, the value is the texture unit
+impl UniformValue for i32 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::INT || gl_type == gl::BOOL || is_sampler(gl_type) }
+    unsafe fn set(&self, location: i32) { gl::Uniform1i(location, *self) }
+}
+impl UniformValue for [i32; 2] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::INT_VEC2 }
+    unsafe fn set(&self, location: i32) { gl::Uniform2iv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for [i32; 3] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::INT_VEC3 }
+    unsafe fn set(&self, location: i32) { gl::Uniform3iv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for [i32; 4] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::INT_VEC4 }
+    unsafe fn set(&self, location: i32) { gl::Uniform4iv(location, 1, self.as_ptr()) }
+}
+impl UniformValue for u32 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::UNSIGNED_INT }
+    unsafe fn set(&self, location: i32) { gl::Uniform1ui(location, *self) }
+}
+impl UniformValue for bool {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::BOOL }
+    unsafe fn set(&self, location: i32) { gl::Uniform1i(location, *self as i32) }
+}
+/// mat3x2, column major
+impl UniformValue for [f32; 6] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_MAT3x2 }
+    unsafe fn set(&self, location: i32) { gl::UniformMatrix3x2fv(location, 1, gl::FALSE, self.as_ptr()) }
+}
+impl UniformValue for [f32; 9] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_MAT3 }
+    unsafe fn set(&self, location: i32) { gl::UniformMatrix3fv(location, 1, gl::FALSE, self.as_ptr()) }
+}
+impl UniformValue for [f32; 16] {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_MAT4 }
+    unsafe fn set(&self, location: i32) { gl::UniformMatrix4fv(location, 1, gl::FALSE, self.as_ptr()) }
+}
+impl UniformValue for Mat3 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_MAT3 }
+    unsafe fn set(&self, location: i32) { gl::UniformMatrix3fv(location, 1, gl::FALSE, self.to_cols_array().as_ptr()) }
+}
+impl UniformValue for Mat4 {
+    fn accepts(gl_type: GLenum) -> bool { gl_type == gl::FLOAT_MAT4 }
+    unsafe fn set(&self, location: i32) { gl::UniformMatrix4fv(location, 1, gl::FALSE, self.to_cols_array().as_ptr()) }
+}
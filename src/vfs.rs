@@ -0,0 +1,241 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+enum MountSource {
+    Directory(PathBuf),
+    /// Reading seeks the file, so it is shared behind a lock
+    Archive(Mutex<ZipArchive<File>>),
+}
+
+struct Mount {
+    /// Real path of the directory or archive
+    path: PathBuf,
+    /// Virtual directory the mount appears under, empty for the root
+    point: String,
+    source: MountSource,
+}
+
+/// Searched first to last, the latest mount comes first so it overrides the earlier ones
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Mounts a directory or a .zip archive under the virtual directory `point` ("" for the root).
+/// Files it contains take priority over the same paths in earlier mounts, which is how mods override
+pub fn mount(path: &str, point: &str) -> io::Result<()> {
+    let point = normalize(point)?;
+    let real = PathBuf::from(path);
+    let source = if real.is_dir() {
+        MountSource::Directory(real.clone())
+    } else {
+        let archive = ZipArchive::new(File::open(&real)?).map_err(zip_error)?;
+        MountSource::Archive(Mutex::new(archive))
+    };
+    MOUNTS.write().unwrap().insert(0, Mount { path: real, point, source });
+    Ok(())
+}
+
+/// Returns false if `path` wasn't mounted
+pub fn unmount(path: &str) -> bool {
+    let mut mounts = MOUNTS.write().unwrap();
+    let count = mounts.len();
+    mounts.retain(|x| x.path != Path::new(path));
+    mounts.len() != count
+}
+
+/// Virtual form of `path`: '/' separated, without "." components and with ".." resolved.
+/// Leaving the root is an error
+pub fn normalize(path: &str) -> io::Result<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" leads outside of the mounts", path)));
+                }
+            }
+            x => parts.push(x),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Part of `path` inside the mount, None if the mount doesn't cover it
+fn relative<'a>(mount: &Mount, path: &'a str) -> Option<&'a str> {
+    if mount.point.is_empty() {
+        return Some(path);
+    }
+    path.strip_prefix(mount.point.as_str())?.strip_prefix('/')
+}
+
+fn zip_error(e: ZipError) -> io::Error {
+    match e {
+        ZipError::Io(e) => e,
+        ZipError::FileNotFound => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("\"{}\" not found in any mount", path))
+}
+
+/// Reads the file from the first mount that has it, absolute paths are read from disk directly.
+/// Errors other than a missing file are reported rather than falling through to the next mount
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    if Path::new(path).is_absolute() {
+        return std::fs::read(path);
+    }
+    let virtual_path = normalize(path)?;
+    for mount in MOUNTS.read().unwrap().iter() {
+        let Some(relative) = relative(mount, &virtual_path) else {
+            continue;
+        };
+        let result = match &mount.source {
+            MountSource::Directory(directory) => std::fs::read(directory.join(relative)),
+            MountSource::Archive(archive) => {
+                let mut archive = archive.lock().unwrap();
+                archive.by_name(relative).map_err(zip_error).and_then(|mut file| {
+                    let mut data = Vec::with_capacity(file.size() as usize);
+                    file.read_to_end(&mut data)?;
+                    Ok(data)
+                })
+            }
+        };
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            x => return x,
+        }
+    }
+    Err(not_found(path))
+}
+
+pub fn read_to_string(path: &str) -> io::Result<String> {
+    String::from_utf8(read(path)?)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("\"{}\" is not valid UTF-8", path)))
+}
+
+/// File on disk `path` resolves to, None if it doesn't exist or comes from an archive
+pub fn real_path(path: &str) -> Option<PathBuf> {
+    if Path::new(path).is_absolute() {
+        return Some(PathBuf::from(path)).filter(|x| x.is_file());
+    }
+    let virtual_path = normalize(path).ok()?;
+    for mount in MOUNTS.read().unwrap().iter() {
+        let Some(relative) = relative(mount, &virtual_path) else {
+            continue;
+        };
+        match &mount.source {
+            MountSource::Directory(directory) => {
+                let real = directory.join(relative);
+                if real.is_file() {
+                    return Some(real);
+                }
+            }
+            MountSource::Archive(archive) => {
+                if archive.lock().unwrap().index_for_name(relative).is_some() {
+                    return None;
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use crate::test_files::TempDir;
+
+    /// Built in memory, then saved since mounts open archives by path
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        std::fs::write(path, zip.finish().unwrap().into_inner()).unwrap();
+    }
+
+    fn mount_at(point: &str) -> Mount {
+        Mount { path: PathBuf::new(), point: point.to_string(), source: MountSource::Directory(PathBuf::new()) }
+    }
+
+    #[test]
+    fn normalize_resolves_components() {
+        assert_eq!(normalize("a/./b//c").unwrap(), "a/b/c");
+        assert_eq!(normalize("a\\b\\..\\c").unwrap(), "a/c");
+        assert_eq!(normalize("/a/b/").unwrap(), "a/b");
+        assert_eq!(normalize("a/..").unwrap(), "");
+        assert_eq!(normalize("").unwrap(), "");
+    }
+
+    #[test]
+    fn normalize_rejects_leaving_the_root() {
+        for path in ["..", "../a", "a/../..", "a/b/../../../c"] {
+            let error = normalize(path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", path);
+        }
+    }
+
+    #[test]
+    fn relative_strips_the_mount_point() {
+        assert_eq!(relative(&mount_at(""), "a/b.png"), Some("a/b.png"));
+        assert_eq!(relative(&mount_at("mods"), "mods/b.png"), Some("b.png"));
+        assert_eq!(relative(&mount_at("mods/extra"), "mods/extra/x/b.png"), Some("x/b.png"));
+        // Only whole components match
+        assert_eq!(relative(&mount_at("mods"), "modsextra/b.png"), None);
+        assert_eq!(relative(&mount_at("mods"), "mods"), None);
+        assert_eq!(relative(&mount_at("mods"), "other/b.png"), None);
+    }
+
+    // Mounts are global, every test uses its own mount point so they don't see each other's files
+    #[test]
+    fn later_mounts_override_earlier_ones() {
+        let dir = TempDir::new("vfs-priority");
+        let point = format!("vfs-priority-{}", std::process::id());
+        let base = dir.join("base");
+        dir.write("base/shared.txt", "base");
+        dir.write("base/only_base.txt", "base");
+        dir.write("base/nested/file.txt", "nested");
+        let archive = dir.join("mod.zip");
+        write_zip(&archive, &[("shared.txt", "mod"), ("only_mod.txt", "mod")]);
+
+        mount(base.to_str().unwrap(), &point).unwrap();
+        let read_at = |name: &str| read_to_string(&format!("{}/{}", point, name));
+        assert_eq!(read_at("shared.txt").unwrap(), "base");
+        assert_eq!(read_at("nested/../nested/file.txt").unwrap(), "nested");
+
+        mount(archive.to_str().unwrap(), &point).unwrap();
+        assert_eq!(read_at("shared.txt").unwrap(), "mod");
+        assert_eq!(read_at("only_base.txt").unwrap(), "base");
+        assert_eq!(read_at("only_mod.txt").unwrap(), "mod");
+        assert_eq!(read_at("missing.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+        // Files in archives have no real path
+        assert_eq!(real_path(&format!("{}/shared.txt", point)), None);
+        assert_eq!(real_path(&format!("{}/only_base.txt", point)), Some(base.join("only_base.txt")));
+
+        assert!(unmount(archive.to_str().unwrap()));
+        assert!(!unmount(archive.to_str().unwrap()));
+        assert_eq!(read_at("shared.txt").unwrap(), "base");
+        assert!(unmount(base.to_str().unwrap()));
+    }
+
+    #[test]
+    fn mount_points_limit_what_a_mount_covers() {
+        let dir = TempDir::new("vfs-points");
+        let point = format!("vfs-points-{}", std::process::id());
+        dir.write("file.txt", "inside");
+
+        mount(dir.path().to_str().unwrap(), &format!("{}/sub", point)).unwrap();
+        assert_eq!(read_to_string(&format!("{}/sub/file.txt", point)).unwrap(), "inside");
+        assert_eq!(read(&format!("{}/file.txt", point)).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(read(&format!("{}/sub/../../../file.txt", point)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(unmount(dir.path().to_str().unwrap()));
+    }
+}